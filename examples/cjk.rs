@@ -5,13 +5,15 @@ use egui::{
     epaint::text::FontInsert, style::Spacing, Color32, FontData, FontFamily, Margin, Stroke, Style,
     Visuals,
 };
-use wpopup::{
-    application::Msg, errors::wrap_noncritical_sync, layer_shell::LayerShellOptions, App,
-    AppCreator,
-};
 use sctk::shell::wlr_layer::{Anchor, KeyboardInteractivity};
 use tokio::sync::watch;
 use tracing::{info, level_filters::LevelFilter};
+use wpopup::{
+    application::{Msg, WPEvent},
+    errors::wrap_noncritical_sync,
+    layer_shell::{LayerShellOptions, SelectionSource, WgpuLayerShellState},
+    App,
+};
 
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
@@ -56,11 +58,45 @@ fn main() -> anyhow::Result<()> {
                     }
                 });
         }
+        fn sync(&mut self, _layer: &WgpuLayerShellState) {}
+    }
+
+    // a second surface, sharing the connection and device with the first one
+    struct BadgeApp;
+
+    impl App for BadgeApp {
+        fn update(&mut self, ctx: &egui::Context) {
+            egui::CentralPanel::default()
+                .frame(egui::Frame::new().fill(Color32::BLACK.gamma_multiply(0.5)))
+                .show(ctx, |ui| {
+                    ui.label("wpopup");
+                });
+        }
+        fn sync(&mut self, _layer: &WgpuLayerShellState) {}
     }
 
     let (msg, mut app) = wpopup::run_layer(
         options,
-        Box::new(|ctx, sx| {
+        Box::new(|ctx, _sx, ev| {
+            std::thread::spawn(move || {
+                wrap_noncritical_sync(|| {
+                    for event in ev.iter() {
+                        let (
+                            WPEvent::Selection {
+                                source: SelectionSource::Primary,
+                                ..
+                            },
+                            Some(text),
+                        ) = (&event, event.text())
+                        else {
+                            continue;
+                        };
+                        info!("select {:?}", text);
+                        p_sx.send(text.to_owned())?;
+                    }
+                    anyhow::Ok(())
+                });
+            });
             let mut li = Visuals::dark();
             li.override_text_color = Some(Color32::WHITE.gamma_multiply(0.8));
             ctx.set_visuals(li);
@@ -70,30 +106,24 @@ fn main() -> anyhow::Result<()> {
                 select: p_rx,
             }))
         }),
-    );
+    )?;
     msg.send(Msg::Passthrough(false))?;
-    let msg2  = msg.clone();
 
-    std::thread::spawn(move || {
-        wrap_noncritical_sync(|| {
-            let mut lis = wayland_clipboard_listener::WlClipboardPasteStream::init(
-                WlListenType::ListenOnSelect,
-            )?;
-            for ctx in lis.paste_stream().flatten() {
-                let stx = String::from_utf8(ctx.context.context);
-                if let Ok(stx) = stx {
-                    info!("select {:?}", &stx);
-                    p_sx.send(stx)?;
-                    msg2.send(Msg::Repaint)?;
-                }
-            }
-            anyhow::Ok(())
-        });
-    });
+    let (_, badge) = app.create_surface(
+        LayerShellOptions {
+            width: 80,
+            height: 30,
+            anchor: Some(Anchor::TOP | Anchor::RIGHT),
+            margin: (10, 10, 10, 10),
+            namespace: "badge".to_owned(),
+            ..Default::default()
+        },
+        Box::new(|_, _, _| Ok(Box::new(BadgeApp))),
+    )?;
 
     std::thread::spawn(move || {
         use std::io::{self, Write};
-        println!("Type 'h' to hide the window, 's' to show it");
+        println!("Type 'h' to hide the window, 's' to show it, 'b' to toggle the badge");
         loop {
             print!("> ");
             io::stdout().flush().unwrap();
@@ -115,6 +145,9 @@ fn main() -> anyhow::Result<()> {
                     "p" => {
                         msg.send(Msg::Passthrough(true))?;
                     }
+                    "b" => {
+                        badge.send(Msg::Toggle)?;
+                    }
                     _ => println!("Unknown command: {}", cmd),
                 }
                 anyhow::Ok(())
@@ -122,7 +155,8 @@ fn main() -> anyhow::Result<()> {
         }
     });
 
-    app.run_forever()?;
+    let reason = app.run_forever()?;
+    info!("exited: {:?}", reason);
 
     Ok(())
 }
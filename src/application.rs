@@ -1,162 +1,442 @@
-use std::{cell::RefCell, io::PipeReader, sync::Arc};
-
-use crossbeam::queue::ArrayQueue;
-use sctk::{
-    reexports::calloop::{self, timer::Timer, EventLoop},
-    shell::{
-        wlr_layer::{Layer, SurfaceKind},
-        WaylandSurface,
-    },
-};
-use tokio::sync::mpsc;
-use tracing::{info, warn};
+use std::{fmt, io::PipeReader, sync::Mutex};
+
+use sctk::reexports::calloop::{self, channel::Channel, EventLoop};
+use tracing::warn;
 
 use crate::{
-    layer_shell::{LayerShellOptions, WgpuLayerShellState},
-    text_input::{ImeCapabilities, ImeEnableRequest},
+    errors::InitError,
+    layer_shell::{
+        selection::is_text_mime, ClipboardHistory, InputMethodCommit, InputMethodEvent, KeyChord,
+        LayerShellOptions, LayerShellOptionsPatch, OutputSelector, SelectionDelivery,
+        SelectionOffers, SelectionSource, SurfaceId, WgpuLayerShellState,
+    },
     App, AppCreator, Result,
 };
 
 pub struct WgpuLayerShellApp {
-    application: RefCell<Box<dyn App>>,
     pub event_loop: EventLoop<'static, WgpuLayerShellState>,
     pub layer_shell_state: WgpuLayerShellState,
 }
 
+/// Messages are applied to the surface owning the [`MsgQueue`] they were sent through.
 #[derive(Debug)]
 pub enum Msg {
     Toggle,
     Hide(bool),
     Passthrough(bool),
+    /// Only take input on the interactive egui areas and windows, see
+    /// [`WgpuLayerShellState::set_auto_passthrough`].
+    AutoPassthrough(bool),
     Repaint,
+    /// See [`LayerShellOptions::exclusive_zone`].
+    ExclusiveZone(i32),
+    /// Destroy all surfaces and return from [`WgpuLayerShellApp::run_forever`].
     Exit,
-    SimulateKey
+    /// Type text into the window with keyboard focus, see [`WgpuLayerShellState::type_text`].
+    TypeText(String),
+    /// Press key chords in the window with keyboard focus, e.g. ctrl+v to paste.
+    SendKeys(Vec<KeyChord>),
+    /// Create another layer surface with its own app.
+    CreateSurface(NewSurface),
+    /// Destroy this surface and drop its app.
+    Close,
+    /// Change the options of this surface, see [`WgpuLayerShellState::reconfigure`].
+    Reconfigure(LayerShellOptionsPatch),
+    /// Move this surface to another output by re-creating its layer surface there.
+    MoveToOutput(OutputSelector),
+    /// Show the popup next to a point, e.g. the pointer or the text caret.
+    ///
+    /// `x` and `y` are logical pixels relative to the output. Without an output the one the
+    /// surface is currently on is used.
+    ShowAt {
+        x: i32,
+        y: i32,
+        output: Option<OutputSelector>,
+    },
+    /// Change how new selections are delivered, see [`SelectionDelivery`].
+    SelectionDelivery(SelectionDelivery),
+    /// Read the current selection as one of the MIME types it was offered in, see
+    /// [`WPEvent::SelectionOffered`].
+    ReadSelection {
+        source: SelectionSource,
+        mime: String,
+    },
+    /// See [`WgpuLayerShellState::set_mime_priority`].
+    MimePriority(Vec<String>),
+    /// Start or stop recording the clipboard, see [`WgpuLayerShellState::clipboard_history`].
+    ClipboardHistory(Option<ClipboardHistory>),
+    /// Make an entry of the clipboard history the clipboard again.
+    RestoreHistoryEntry(usize),
+    /// Set the clipboard or primary selection, see [`WgpuLayerShellState::set_selection`].
+    SetClipboard {
+        target: SelectionSource,
+        offers: SelectionOffers,
+    },
+    /// Act as the input method of every seat, see [`WgpuLayerShellState::set_input_method`].
+    InputMethod(bool),
+    /// Change the text of the text input served by the input method.
+    InputMethodCommit(InputMethodCommit),
+    /// Create an input popup with its own app, shown at the text cursor while the input method
+    /// of the named seat, or of the first seat, is active. Requires [`Msg::InputMethod`].
+    CreateInputPopup {
+        seat: Option<String>,
+        surface: NewSurface,
+    },
+}
+
+/// Why [`WgpuLayerShellApp::run_forever`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// [`Msg::Exit`] was sent to this surface.
+    Exit(SurfaceId),
+    /// The last surface was closed with [`Msg::Close`].
+    AllSurfacesClosed,
 }
 
+/// Sent to every [`EvRx`], each gets its own copy.
 #[derive(Debug)]
 pub enum WPEvent {
+    /// A new selection to read yourself, with [`SelectionDelivery::Fd`]. Every [`EvRx`] gets a
+    /// descriptor of its own for the pipe, only one of them should read it.
     Fd(PipeReader),
+    /// A new selection is available in these MIME types, sent before it is read.
+    SelectionOffered {
+        source: SelectionSource,
+        seat: Option<String>,
+        mimes: Vec<String>,
+    },
+    /// A selection read in full, new ones with [`SelectionDelivery::Read`] or one asked for
+    /// with [`Msg::ReadSelection`].
+    Selection {
+        source: SelectionSource,
+        mime: String,
+        /// Name of the seat the selection was made on.
+        seat: Option<String>,
+        data: Vec<u8>,
+    },
+    /// The compositor connection was lost and has been re-established. All surfaces are
+    /// re-created, the apps are kept.
+    Reconnected,
+    Surface(SurfaceId, SurfaceEvent),
+    /// Another client replaced the selection set with [`Msg::SetClipboard`].
+    SelectionCancelled(SelectionSource),
+    /// The text input served by the input method of a seat changed, see [`Msg::InputMethod`].
+    InputMethod {
+        seat: Option<String>,
+        event: InputMethodEvent,
+    },
+}
+
+impl WPEvent {
+    /// The selection as text, if it was offered as text and is valid UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            WPEvent::Selection { mime, data, .. } if is_text_mime(mime) => {
+                std::str::from_utf8(data).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// A copy for one more [`EvRx`], `None` if the pipe could not be duplicated.
+    pub(crate) fn duplicate(&self) -> Option<Self> {
+        Some(match self {
+            WPEvent::Fd(reader) => WPEvent::Fd(reader.try_clone().ok()?),
+            WPEvent::Selection {
+                source,
+                mime,
+                seat,
+                data,
+            } => WPEvent::Selection {
+                source: *source,
+                mime: mime.clone(),
+                seat: seat.clone(),
+                data: data.clone(),
+            },
+            WPEvent::SelectionOffered {
+                source,
+                seat,
+                mimes,
+            } => WPEvent::SelectionOffered {
+                source: *source,
+                seat: seat.clone(),
+                mimes: mimes.clone(),
+            },
+            WPEvent::SelectionCancelled(source) => WPEvent::SelectionCancelled(*source),
+            WPEvent::Reconnected => WPEvent::Reconnected,
+            WPEvent::InputMethod { seat, event } => WPEvent::InputMethod {
+                seat: seat.clone(),
+                event: event.clone(),
+            },
+            WPEvent::Surface(id, event) => WPEvent::Surface(*id, event.clone()),
+        })
+    }
+}
+
+/// Lifecycle of a surface, sent as [`WPEvent::Surface`].
+///
+/// Dropped while no [`EvRx`] is alive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SurfaceEvent {
+    /// New size in logical pixels.
+    Configured {
+        width: u32,
+        height: u32,
+    },
+    /// The keyboard of a seat, named if the compositor tells, entered the surface.
+    FocusGained {
+        seat: Option<String>,
+    },
+    FocusLost {
+        seat: Option<String>,
+    },
+    /// The surface is now shown on the output with this name.
+    OutputEntered(Option<String>),
+    OutputLeft(Option<String>),
+    /// The compositor closed the layer surface, it is about to be re-created.
+    Closed,
+    /// A new layer surface replaced the previous one.
+    Recreated,
+    /// Removed with [`Msg::Close`] or on exit, no more events follow.
+    Destroyed,
+    PointerEntered {
+        seat: Option<String>,
+    },
+    PointerLeft {
+        seat: Option<String>,
+    },
+    Shown,
+    Hidden,
+    /// Where the text being entered is relative to an input popup, in logical pixels.
+    TextInputRectangle {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
 }
 
 pub type MsgQueue = calloop::channel::Sender<Msg>;
 pub type EvRx = flume::Receiver<WPEvent>;
 
+/// Like [`AppCreator`], but can be sent to the event loop from another thread.
+pub type SurfaceCreator =
+    Box<dyn FnOnce(&egui::Context, MsgQueue, EvRx) -> anyhow::Result<Box<dyn App>> + Send>;
+
+/// A surface to be created at runtime through [`Msg::CreateSurface`].
+///
+/// The id and message queue are known before the surface exists, so the sender can keep
+/// addressing it afterwards.
+pub struct NewSurface {
+    id: SurfaceId,
+    options: LayerShellOptions,
+    sender: MsgQueue,
+    // Mutex keeps `Msg` Sync, so send errors still convert into `anyhow::Error`.
+    inner: Mutex<(SurfaceCreator, Channel<Msg>)>,
+}
+
+impl NewSurface {
+    pub fn new(options: LayerShellOptions, creator: SurfaceCreator) -> Self {
+        let (sender, channel) = calloop::channel::channel();
+        Self {
+            id: SurfaceId::next(),
+            options,
+            sender,
+            inner: Mutex::new((creator, channel)),
+        }
+    }
+
+    pub fn id(&self) -> SurfaceId {
+        self.id
+    }
+
+    pub fn msg_queue(&self) -> MsgQueue {
+        self.sender.clone()
+    }
+}
+
+impl fmt::Debug for NewSurface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NewSurface")
+            .field("id", &self.id)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) fn handle_msg(data: &mut WgpuLayerShellState, id: SurfaceId, m: Msg) {
+    match m {
+        Msg::TypeText(text) => {
+            data.type_text(id, &text);
+        }
+        Msg::SendKeys(chords) => {
+            data.send_keys(id, &chords);
+        }
+        Msg::Toggle => {
+            if let Some(surface) = data.surface_mut(id) {
+                surface.set_hidden(!surface.is_hidden());
+            }
+        }
+        Msg::Hide(b) => {
+            if let Some(surface) = data.surface_mut(id) {
+                surface.set_hidden(b);
+            }
+        }
+        Msg::Passthrough(b) => {
+            data.set_passthrough(id, b);
+        }
+        Msg::AutoPassthrough(b) => {
+            data.set_auto_passthrough(id, b);
+        }
+        Msg::ExclusiveZone(zone) => {
+            if let Some(surface) = data.surface_mut(id) {
+                surface.set_exclusive_zone(zone);
+            }
+        }
+        Msg::Repaint => {
+            if let Some(surface) = data.surface(id) {
+                surface.egui_state.context().request_repaint();
+            }
+        }
+        Msg::Exit => {
+            data.exit = Some(ExitReason::Exit(id));
+        }
+        Msg::CreateSurface(new) => {
+            let NewSurface {
+                id,
+                options,
+                sender,
+                inner,
+            } = new;
+            let (creator, channel) = inner.into_inner().unwrap();
+            if let Err(e) = data.add_surface(id, options, creator, (sender, channel)) {
+                warn!("could not create surface {:?}: {:?}", id, e);
+            }
+        }
+        Msg::Close => {
+            data.remove_surface(id);
+        }
+        Msg::ShowAt { x, y, output } => {
+            data.show_at(id, x, y, output);
+        }
+        Msg::Reconfigure(patch) => {
+            data.reconfigure(id, patch);
+        }
+        Msg::MoveToOutput(selector) => {
+            if let Some(surface) = data.surface_mut(id) {
+                surface.layer_opts.output = Some(selector);
+                surface.recreate = true;
+            }
+        }
+        Msg::SelectionDelivery(delivery) => {
+            data.set_selection_delivery(delivery);
+        }
+        Msg::ReadSelection { source, mime } => {
+            data.read_selection(source, mime);
+        }
+        Msg::MimePriority(priority) => {
+            data.set_mime_priority(priority);
+        }
+        Msg::ClipboardHistory(history) => {
+            data.set_clipboard_history(history);
+        }
+        Msg::RestoreHistoryEntry(index) => {
+            data.restore_history_entry(index);
+        }
+        Msg::SetClipboard { target, offers } => {
+            data.set_selection(target, offers);
+        }
+        Msg::InputMethod(enabled) => {
+            data.set_input_method(enabled);
+        }
+        Msg::InputMethodCommit(commit) => {
+            data.input_method_commit(commit);
+        }
+        Msg::CreateInputPopup { seat, surface } => {
+            let NewSurface {
+                id,
+                options,
+                sender,
+                inner,
+            } = surface;
+            let (creator, channel) = inner.into_inner().unwrap();
+            if let Err(e) =
+                data.add_input_popup(id, seat.as_deref(), options, creator, (sender, channel))
+            {
+                warn!("could not create input popup {:?}: {:?}", id, e);
+            }
+        }
+    }
+}
+
 impl WgpuLayerShellApp {
     pub fn new(
         layer_shell_options: LayerShellOptions,
         app_creator: AppCreator,
-    ) -> (MsgQueue, EvRx, Self) {
-        let event_loop = EventLoop::try_new().expect("Could not create event loop.");
-        let (sx, rx) = calloop::channel::channel::<Msg>();
+    ) -> Result<(MsgQueue, EvRx, Self), InitError> {
+        let event_loop = EventLoop::try_new().map_err(InitError::EventLoop)?;
         let (esx, erx) = flume::unbounded();
-        let hd = event_loop.handle();
-        let sx1 = sx.clone();
-
-        event_loop
-            .handle()
-            .insert_source(rx, move |e, a, data: &mut WgpuLayerShellState| match e {
-                calloop::channel::Event::Msg(m) => {
-                    info!("{:?}", &m);
-                    match m {
-                        Msg::SimulateKey => {
-                            data.simulate_key();
-                        }
-                        Msg::Toggle => {
-                            if data.current_layer != Layer::Background {
-                                data.current_layer = Layer::Background;
-                                data.layer.set_layer(Layer::Background);
-                                data.layer.commit();
-                                data.egui_state.context().request_repaint();
-                            } else {
-                                data.current_layer = Layer::Overlay;
-                                data.layer.set_layer(Layer::Overlay);
-                                data.layer.commit();
-                                data.egui_state.context().request_repaint();
-                            }
-                        }
-                        Msg::Hide(b) => {
-                            if b {
-                                data.current_layer = Layer::Background;
-                                data.layer.set_layer(Layer::Background);
-                                data.layer.commit();
-                                data.egui_state.context().request_repaint();
-                            } else {
-                                data.current_layer = Layer::Overlay;
-                                data.layer.set_layer(Layer::Overlay);
-                                data.layer.commit();
-                                data.egui_state.context().request_repaint();
-                            }
-                        }
-                        Msg::Passthrough(b) => {
-                            data.set_passthrough(b);
-                        }
-                        Msg::Repaint => {
-                            data.egui_state.context().request_repaint();
-                        }
-                        Msg::Exit => {
-                            sx1.send(Msg::Hide(true)).unwrap();
-                            hd.insert_source(Timer::immediate(), |e, m, d| {
-                                std::process::exit(0);
-                            })
-                            .unwrap();
-                        }
-                    }
-                }
-                _ => (),
-            })
-            .unwrap();
-        let layer_shell_state =
-            WgpuLayerShellState::new(event_loop.handle(), layer_shell_options, esx);
-        let app = RefCell::new(
-            app_creator(
-                &layer_shell_state.egui_state.context(),
-                sx.clone(),
-                erx.clone(),
-            )
-            .expect("could not create app"),
-        );
-        app.borrow()
-            .init(layer_shell_state.egui_state.context(), &layer_shell_state);
-        (
-            sx,
-            erx,
-            Self {
-                // TODO: find better way to handle this potential error
-                application: app,
-                event_loop,
-                layer_shell_state,
-            },
-        )
+
+        let mut layer_shell_state = WgpuLayerShellState::new(event_loop.handle(), esx, erx)?;
+        let erx = layer_shell_state.subscribe();
+        let mut app = Self {
+            event_loop,
+            layer_shell_state,
+        };
+        let (_, sx) = app.create_surface(layer_shell_options, app_creator)?;
+
+        Ok((sx, erx, app))
     }
 
-    pub fn run_forever(mut self) -> Result {
+    /// Adds a layer surface before the loop is started. Surfaces can also be created at runtime
+    /// with [`Msg::CreateSurface`].
+    pub fn create_surface(
+        &mut self,
+        options: LayerShellOptions,
+        app_creator: AppCreator,
+    ) -> Result<(SurfaceId, MsgQueue), InitError> {
+        let id = SurfaceId::next();
+        let (sx, rx) = calloop::channel::channel::<Msg>();
+        self.layer_shell_state
+            .add_surface(id, options, app_creator, (sx.clone(), rx))?;
+        Ok((id, sx))
+    }
+
+    /// Runs the event loop until [`Msg::Exit`] is received or all surfaces are closed.
+    pub fn run_forever(mut self) -> Result<ExitReason> {
         loop {
-            self.event_loop
-                .dispatch(
-                    self.layer_shell_state.get_timeout(),
-                    &mut self.layer_shell_state,
-                )
-                .unwrap();
-
-            if self.layer_shell_state.should_draw() {
-                let mut application = self.application.borrow_mut();
-                self.layer_shell_state.draw(&mut **application);
+            let dispatched = self.event_loop.dispatch(
+                self.layer_shell_state.get_timeout(),
+                &mut self.layer_shell_state,
+            );
+            if let Err(err) = dispatched {
+                if !self.layer_shell_state.connection_lost() {
+                    return Err(err.into());
+                }
+                warn!("lost connection to the compositor: {}", err);
+                self.layer_shell_state.reconnect()?;
             }
 
-            // For some reason the layer get destroyed externally. Usually after resuming from computer suspension.
-            if self.layer_shell_state.exit {
-                warn!("layershell exited. restarting..");
-                self.layer_shell_state = WgpuLayerShellState::new(
-                    self.layer_shell_state.loop_handle,
-                    self.layer_shell_state.layer_opts,
-                    self.layer_shell_state.ev,
-                );
-                self.application.borrow().init(
-                    self.layer_shell_state.egui_state.context(),
-                    &self.layer_shell_state,
+            if let Some(err) = self.layer_shell_state.reconnect_error.take() {
+                return Err(
+                    anyhow::Error::new(err).context("could not reconnect to the compositor")
                 );
             }
+
+            if let Some(reason) = self.layer_shell_state.exit.take() {
+                self.layer_shell_state.close_all();
+                self.layer_shell_state.deliver_events();
+                return Ok(reason);
+            }
+
+            // Surfaces wait for the new connection.
+            if !self.layer_shell_state.reconnecting {
+                self.layer_shell_state.draw_pending();
+
+                self.layer_shell_state.recreate_surfaces();
+            }
+
+            self.layer_shell_state.deliver_events();
         }
-        Ok(())
     }
 }
@@ -1,4 +1,11 @@
-use egui::{epaint::ClippedShape, Context, FullOutput, TexturesDelta};
+use egui::{
+    emath::TSTransform,
+    epaint::{text::cursor::CCursor, ClippedShape, Primitive},
+    output::OutputEvent,
+    text_edit::TextEditState,
+    text_selection::CCursorRange,
+    Context, FullOutput, Id, TexturesDelta, WidgetType,
+};
 use egui_wgpu::{
     wgpu::{
         CommandEncoder, Device, LoadOp, Operations, Queue, RenderPassColorAttachment,
@@ -9,6 +16,7 @@ use egui_wgpu::{
 use tracing::info;
 
 use crate::layer_shell::WgpuLayerShellState;
+use crate::text_input::ImeSurroundingText;
 
 // crates/egui-winit/src/lib.rs
 pub struct State {
@@ -23,6 +31,12 @@ pub struct State {
     pub allow_ime: bool,
     pub ime_rect_px: Option<egui::Rect>,
     pub pointer_pos_in_points: Option<egui::Pos2>,
+    ime_text: ImeText,
+    /// Surface size in logical pixels, turned into the screen rect in points on every frame.
+    logical_size: Option<(u32, u32)>,
+    /// Applied to everything drawn, used by show and hide transitions.
+    alpha: f32,
+    transform: TSTransform,
 }
 
 impl State {
@@ -51,6 +65,105 @@ impl State {
             .push(egui::Event::Ime(egui::ImeEvent::Disabled));
     }
 
+    /// Follows the focused `TextEdit` through the output of a frame.
+    pub(crate) fn update_ime_text(&mut self, events: &[OutputEvent]) {
+        let focused = self
+            .context
+            .memory(|m| m.focused())
+            .filter(|id| TextEditState::load(&self.context, *id).is_some());
+        let reported = events
+            .iter()
+            .map(OutputEvent::widget_info)
+            .filter(|info| info.typ == WidgetType::TextEdit)
+            .filter_map(|info| info.current_text_value.as_ref())
+            .next_back();
+        let ime_text = &mut self.ime_text;
+        if focused != ime_text.id {
+            *ime_text = ImeText {
+                id: focused,
+                ..Default::default()
+            };
+        }
+        if let Some(text) = reported {
+            ime_text.text.clone_from(text);
+        }
+        let Some(id) = ime_text.id else {
+            return;
+        };
+        let Some(mut state) = TextEditState::load(&self.context, id) else {
+            return;
+        };
+        let Some(range) = state.cursor.char_range() else {
+            return;
+        };
+
+        // Egui selects the whole preedit, the cursor the IME asked for is placed once it
+        // is shown.
+        if let Some((begin, end)) = ime_text.preedit_cursor.take() {
+            let start = range.as_sorted_char_range().start;
+            let cursor = CCursorRange::two(CCursor::new(start + begin), CCursor::new(start + end));
+            state.cursor.set_char_range(Some(cursor));
+            state.store(&self.context, id);
+            ime_text.placed = Some((start, cursor));
+            self.context.request_repaint();
+        }
+
+        ime_text.surrounding = ime_text.surrounding_text(range);
+    }
+
+    /// The text around the cursor of the focused `TextEdit`, as of the last frame.
+    pub(crate) fn ime_surrounding_text(&self) -> Option<&ImeSurroundingText> {
+        self.ime_text.surrounding.as_ref()
+    }
+
+    /// Selects the preedit of the focused `TextEdit`, widened by `before` and `after` bytes
+    /// of the text around it, so that an empty preedit removes them.
+    ///
+    /// Returns `false` when there is nothing to remove.
+    pub(crate) fn select_ime_replacement(&mut self, before: usize, after: usize) -> bool {
+        let ime_text = &mut self.ime_text;
+        let preedit_len = std::mem::take(&mut ime_text.preedit_len);
+        let placed = ime_text.placed.take();
+        ime_text.preedit_cursor = None;
+        let Some(id) = ime_text.id else {
+            return false;
+        };
+        let Some(mut state) = TextEditState::load(&self.context, id) else {
+            return false;
+        };
+        let Some(range) = state.cursor.char_range() else {
+            return false;
+        };
+        let (start, end) = match placed {
+            Some((start, cursor)) if cursor == range => (start, start + preedit_len),
+            _ if preedit_len > 0 => {
+                let sorted = range.as_sorted_char_range();
+                (sorted.start, sorted.end)
+            }
+            _ => (range.primary.index, range.primary.index),
+        };
+        // The text reported by egui may lag behind, deleting nothing beats deleting too much.
+        let text = &ime_text.text;
+        let before = chars_before(text, start, before).unwrap_or(0);
+        let after = chars_after(text, end, after).unwrap_or(0);
+        if start == end && before == 0 && after == 0 {
+            return false;
+        }
+        let selection = CCursorRange::two(CCursor::new(start - before), CCursor::new(end + after));
+        state.cursor.set_char_range(Some(selection));
+        state.store(&self.context, id);
+        true
+    }
+
+    /// Remembers the preedit pushed to egui, `cursor` being in bytes of `preedit`.
+    pub(crate) fn set_ime_preedit(&mut self, preedit: &str, cursor: Option<(usize, usize)>) {
+        let chars = |bytes: usize| preedit.get(..bytes).map(|s| s.chars().count());
+        self.ime_text.preedit_len = preedit.chars().count();
+        self.ime_text.preedit_cursor =
+            cursor.and_then(|(begin, end)| Some((chars(begin)?, chars(end)?)));
+        self.ime_text.placed = None;
+    }
+
     pub fn new(
         context: egui::Context,
         device: &Device,
@@ -93,18 +206,33 @@ impl State {
             allow_ime: false,
             ime_rect_px: None,
             pointer_pos_in_points: None,
+            ime_text: ImeText::default(),
+            logical_size: None,
+            alpha: 1.,
+            transform: TSTransform::IDENTITY,
         }
     }
 
-    pub fn set_size(&mut self, width: u32, height: u32) {
-        let screen_rect = egui::Rect {
-            min: egui::Pos2 { x: 0f32, y: 0f32 },
-            max: egui::Pos2 {
-                x: width as f32,
-                y: height as f32,
-            },
-        };
-        self.egui_input.screen_rect = Some(screen_rect);
+    /// `width` and `height` are in logical pixels, `scale` is the native pixels per logical pixel.
+    pub fn set_size(&mut self, width: u32, height: u32, scale: f32) {
+        self.logical_size = Some((width, height));
+        self.egui_input
+            .viewports
+            .entry(egui::ViewportId::ROOT)
+            .or_default()
+            .native_pixels_per_point = Some(scale);
+    }
+
+    /// Fades and transforms, in points, the frames drawn from now on.
+    pub fn set_frame_transform(&mut self, alpha: f32, transform: TSTransform) {
+        self.alpha = alpha;
+        self.transform = transform;
+    }
+
+    /// Logical pixels are points scaled by the egui zoom factor.
+    pub fn logical_to_points(&self, x: f64, y: f64) -> egui::Pos2 {
+        let zoom = self.context.zoom_factor();
+        egui::pos2(x as f32 / zoom, y as f32 / zoom)
     }
 
     pub(crate) fn input(&mut self) -> &mut egui::RawInput {
@@ -126,6 +254,10 @@ impl State {
     pub fn process_events(&mut self, run_ui: impl FnMut(&Context)) -> FullOutput {
         // TODO: maybe we need to take input for a certain window / surface?
         self.egui_input.time = Some(self.start_time.elapsed().as_secs_f64());
+        if let Some((width, height)) = self.logical_size {
+            let size = self.logical_to_points(width as f64, height as f64);
+            self.egui_input.screen_rect = Some(egui::Rect::from_min_max(egui::Pos2::ZERO, size));
+        }
 
         let raw_input = self.egui_input.take();
         /* if (&raw_input.events).len() > 0 {
@@ -157,9 +289,20 @@ impl State {
         // this is for things like clipboard support
         //self.state.handle_platform_output(window, full_output.platform_output);
 
-        let tris = self
+        let mut tris = self
             .context
             .tessellate(shapes, self.context.pixels_per_point());
+        if self.alpha < 1. || self.transform != TSTransform::IDENTITY {
+            for clipped in &mut tris {
+                clipped.clip_rect = self.transform * clipped.clip_rect;
+                if let Primitive::Mesh(mesh) = &mut clipped.primitive {
+                    mesh.transform(self.transform);
+                    for vertex in &mut mesh.vertices {
+                        vertex.color = vertex.color.gamma_multiply(self.alpha);
+                    }
+                }
+            }
+        }
         for (id, image_delta) in &textures_delta.set {
             self.renderer
                 .update_texture(device, queue, *id, image_delta);
@@ -189,3 +332,195 @@ impl State {
         }
     }
 }
+
+/// What the IME knows of the focused `TextEdit`.
+#[derive(Debug, Default)]
+struct ImeText {
+    id: Option<Id>,
+    /// The text as last reported by egui, including any preedit.
+    text: String,
+    /// Chars of the preedit shown in the text.
+    preedit_len: usize,
+    /// Cursor within the preedit in chars, to place once egui shows the preedit.
+    preedit_cursor: Option<(usize, usize)>,
+    /// Start of the preedit and the cursor placed within it.
+    placed: Option<(usize, CCursorRange)>,
+    surrounding: Option<ImeSurroundingText>,
+}
+
+impl ImeText {
+    /// The text around the cursor without the preedit, in bytes as the IME expects.
+    fn surrounding_text(&self, range: CCursorRange) -> Option<ImeSurroundingText> {
+        let preedit = match self.placed {
+            Some((start, _)) => Some(start..start + self.preedit_len),
+            None if self.preedit_len > 0 => Some(range.as_sorted_char_range()),
+            None => None,
+        };
+        let mut text = self.text.clone();
+        let (cursor, anchor) = match preedit {
+            Some(preedit) => {
+                let start = byte_index(&text, preedit.start);
+                text.replace_range(start..byte_index(&text, preedit.end), "");
+                (start, start)
+            }
+            None => (
+                byte_index(&text, range.primary.index),
+                byte_index(&text, range.secondary.index),
+            ),
+        };
+
+        // Long texts are cut down to an excerpt around the cursor.
+        let half = ImeSurroundingText::MAX_TEXT_BYTES / 2 - 1;
+        let start = floor_boundary(&text, cursor.saturating_sub(half));
+        let end = floor_boundary(&text, cursor + half);
+        text.truncate(end);
+        text.drain(..start);
+        let anchor = anchor.clamp(start, end) - start;
+        ImeSurroundingText::new(text, cursor - start, anchor).ok()
+    }
+}
+
+/// The char boundary at or before byte `index`, the end of `text` past it.
+fn floor_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Byte offset of char `index`, the end of `text` past it.
+fn byte_index(text: &str, index: usize) -> usize {
+    text.char_indices()
+        .nth(index)
+        .map_or(text.len(), |(i, _)| i)
+}
+
+/// Chars making up the `bytes` before char `index`, `None` off a char boundary.
+fn chars_before(text: &str, index: usize, bytes: usize) -> Option<usize> {
+    let end = byte_index(text, index);
+    let start = end.checked_sub(bytes)?;
+    text.get(start..end).map(|s| s.chars().count())
+}
+
+/// Chars making up the `bytes` from char `index` on, `None` off a char boundary.
+fn chars_after(text: &str, index: usize, bytes: usize) -> Option<usize> {
+    let start = byte_index(text, index);
+    text.get(start..start + bytes).map(|s| s.chars().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_indices() {
+        let cases = [
+            ("abc", 0, 0),
+            ("abc", 2, 2),
+            ("abc", 3, 3),
+            ("abc", 9, 3),
+            ("aé€😀", 1, 1),
+            ("aé€😀", 2, 3),
+            ("aé€😀", 3, 6),
+            ("aé€😀", 4, 10),
+            ("日本語", 2, 6),
+            ("", 1, 0),
+        ];
+        for (text, index, byte) in cases {
+            assert_eq!(byte_index(text, index), byte, "{:?}[{}]", text, index);
+        }
+    }
+
+    #[test]
+    fn floor_boundaries() {
+        let cases = [
+            ("abc", 1, 1),
+            ("abc", 9, 3),
+            ("日本語", 3, 3),
+            ("日本語", 4, 3),
+            ("日本語", 5, 3),
+            ("a😀", 4, 1),
+            ("a😀", 5, 5),
+        ];
+        for (text, index, boundary) in cases {
+            assert_eq!(
+                floor_boundary(text, index),
+                boundary,
+                "{:?}[{}]",
+                text,
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn chars_around() {
+        let cases = [
+            // text, char index, bytes, chars before, chars after
+            ("abcd", 2, 2, Some(2), Some(2)),
+            ("abcd", 2, 3, None, None),
+            ("日本語です", 2, 6, Some(2), Some(2)),
+            ("日本語です", 2, 3, Some(1), Some(1)),
+            ("日本語です", 2, 4, None, None),
+            ("aé€", 2, 3, Some(2), Some(1)),
+            ("aé€", 3, 5, Some(2), None),
+            ("😀x", 1, 4, Some(1), None),
+        ];
+        for (text, index, bytes, before, after) in cases {
+            let case = format!("{:?}[{}] {} bytes", text, index, bytes);
+            assert_eq!(chars_before(text, index, bytes), before, "{}", case);
+            assert_eq!(chars_after(text, index, bytes), after, "{}", case);
+        }
+    }
+
+    fn ime_text(text: &str) -> ImeText {
+        ImeText {
+            text: text.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn range(primary: usize, secondary: usize) -> CCursorRange {
+        CCursorRange::two(CCursor::new(secondary), CCursor::new(primary))
+    }
+
+    #[test]
+    fn surrounding_text_in_bytes() {
+        let cases = [
+            // text, cursor and anchor in chars, then in bytes
+            ("hello", (2, 2), (2, 2)),
+            ("日本語", (1, 3), (3, 9)),
+            ("aé€😀", (4, 1), (10, 1)),
+        ];
+        for (text, (cursor, anchor), bytes) in cases {
+            let sent = ime_text(text)
+                .surrounding_text(range(cursor, anchor))
+                .unwrap();
+            assert_eq!(sent.text(), text);
+            assert_eq!((sent.cursor(), sent.anchor()), bytes, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn surrounding_text_leaves_out_preedit() {
+        let mut ime = ime_text("日本ご語");
+        ime.preedit_len = 1;
+        ime.placed = Some((2, range(3, 3)));
+        let sent = ime.surrounding_text(range(3, 3)).unwrap();
+        assert_eq!(sent.text(), "日本語");
+        assert_eq!((sent.cursor(), sent.anchor()), (6, 6));
+    }
+
+    #[test]
+    fn surrounding_text_window_on_char_boundaries() {
+        let text = "日".repeat(3000);
+        let sent = ime_text(&text).surrounding_text(range(1500, 0)).unwrap();
+        assert!(sent.text().len() < ImeSurroundingText::MAX_TEXT_BYTES);
+        assert!(sent.text().chars().all(|c| c == '日'));
+        // The window starts 1999 bytes before the cursor, rounded down to a char.
+        let start = floor_boundary(&text, 4500 - 1999);
+        assert_eq!(sent.cursor(), 4500 - start);
+        assert_eq!(sent.anchor(), 0);
+    }
+}
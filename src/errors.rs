@@ -1,6 +1,34 @@
 use std::{fmt::Debug, future::Future};
 
 pub use anyhow::Ok as aok;
+use sctk::reexports::calloop;
+use thiserror::Error;
+use wayland_client::{globals::GlobalError, ConnectError};
+
+pub use crate::wgpu_state::WgpuStateError;
+
+/// Why the layer shell app or one of its surfaces could not be set up.
+#[derive(Error, Debug)]
+pub enum InitError {
+    #[error("Could not create the event loop: {0}")]
+    EventLoop(#[source] calloop::Error),
+    #[error("Could not connect to the compositor: {0}")]
+    Connect(#[from] ConnectError),
+    #[error("Could not list the globals of the compositor: {0}")]
+    Globals(#[from] GlobalError),
+    #[error("Could not register an event source: {0}")]
+    EventSource(#[source] calloop::Error),
+    #[error("The compositor does not support {0}")]
+    MissingGlobal(&'static str),
+    #[error("No seat has an input method to show the input popup for")]
+    NoInputMethod,
+    #[error(transparent)]
+    Wgpu(#[from] WgpuStateError),
+    #[error("The app creator failed: {0}")]
+    App(#[source] anyhow::Error),
+    #[error("Could not run the layer shell thread: {0}")]
+    Thread(#[source] std::io::Error),
+}
 
 /// Turn -> Result into -> ()
 /// Handle these non critical errors by logging.
@@ -0,0 +1,139 @@
+use egui::{Id, Vec2};
+use sctk::shell::WaylandSurface;
+use wgpu::Device;
+
+use super::{PopupSurface, SurfaceRole};
+
+/// Lets the egui content drive the size of the layer surface.
+///
+/// The content size is taken from [`egui::Context::used_size`], which only shrinks when the
+/// content is laid out in areas or windows rather than panels filling the whole surface.
+/// Apps can report a size of their own with [`set_content_size`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoSize {
+    /// Smallest size in logical pixels.
+    pub min: (u32, u32),
+    /// Largest size in logical pixels.
+    pub max: (u32, u32),
+    /// The surface grows right away but only shrinks once the content got smaller by more
+    /// than this many logical pixels.
+    pub hysteresis: u32,
+}
+
+impl Default for AutoSize {
+    fn default() -> Self {
+        Self {
+            min: (1, 1),
+            max: (u32::MAX, u32::MAX),
+            hysteresis: 8,
+        }
+    }
+}
+
+impl AutoSize {
+    /// Returns the size to request, or `None` to keep `current`.
+    pub fn next_size(&self, content: (u32, u32), current: (u32, u32)) -> Option<(u32, u32)> {
+        let axis = |content: u32, current: u32, min: u32, max: u32| {
+            let target = content.clamp(min, max.max(min));
+            if target > current || current - target > self.hysteresis {
+                target
+            } else {
+                current
+            }
+        };
+        let next = (
+            axis(content.0, current.0, self.min.0, self.max.0),
+            axis(content.1, current.1, self.min.1, self.max.1),
+        );
+        (next != current).then_some(next)
+    }
+}
+
+fn content_size_id() -> Id {
+    Id::new("wpopup_content_size")
+}
+
+/// Reports the size in points the content needs this frame, overriding
+/// [`egui::Context::used_size`] for [`AutoSize`]. Call it from [`crate::App::update`].
+pub fn set_content_size(ctx: &egui::Context, size: Vec2) {
+    ctx.data_mut(|d| d.insert_temp(content_size_id(), size));
+}
+
+/// Size in logical pixels the content laid out in the current pass needs.
+pub(crate) fn content_size(ctx: &egui::Context) -> Vec2 {
+    let points = ctx
+        .data_mut(|d| d.remove_temp::<Vec2>(content_size_id()))
+        .unwrap_or_else(|| ctx.used_size());
+    points * ctx.zoom_factor()
+}
+
+impl PopupSurface {
+    /// Resizes the layer surface to `content`, in logical pixels.
+    ///
+    /// Input popups take the size of their buffer, which is resized right away.
+    pub(crate) fn auto_size(&mut self, content: Vec2, device: &Device) {
+        let Some(auto_size) = self.layer_opts.auto_size else {
+            return;
+        };
+        let content = (content.x.ceil() as u32, content.y.ceil() as u32);
+        let current = self
+            .auto_size_requested
+            .unwrap_or((self.layer_opts.width, self.layer_opts.height));
+        if let Some((width, height)) = auto_size.next_size(content, current) {
+            self.auto_size_requested = Some((width, height));
+            match &self.role {
+                SurfaceRole::Layer(layer) => {
+                    layer.set_size(width, height);
+                    layer.commit();
+                }
+                SurfaceRole::InputPopup(_) => {
+                    self.logical_size = (width, height);
+                    self.apply_size(device);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auto_size(min: (u32, u32), max: (u32, u32)) -> AutoSize {
+        AutoSize {
+            min,
+            max,
+            hysteresis: 8,
+        }
+    }
+
+    #[test]
+    fn grows_right_away() {
+        let size = auto_size((1, 1), (1000, 1000));
+        assert_eq!(size.next_size((301, 200), (300, 200)), Some((301, 200)));
+    }
+
+    #[test]
+    fn keeps_size_when_shrinking_within_hysteresis() {
+        let size = auto_size((1, 1), (1000, 1000));
+        assert_eq!(size.next_size((292, 195), (300, 200)), None);
+    }
+
+    #[test]
+    fn shrinks_beyond_hysteresis() {
+        let size = auto_size((1, 1), (1000, 1000));
+        assert_eq!(size.next_size((291, 200), (300, 200)), Some((291, 200)));
+    }
+
+    #[test]
+    fn clamps_to_min_and_max() {
+        let size = auto_size((100, 50), (400, 300));
+        assert_eq!(size.next_size((10, 1000), (200, 200)), Some((100, 300)));
+    }
+
+    #[test]
+    fn min_wins_over_smaller_max() {
+        let size = auto_size((200, 100), (100, 50));
+        assert_eq!(size.next_size((500, 10), (0, 0)), Some((200, 100)));
+    }
+}
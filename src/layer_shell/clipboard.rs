@@ -0,0 +1,381 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Write},
+    os::fd::OwnedFd,
+    sync::Arc,
+};
+
+use egui::{ColorImage, OutputCommand};
+use sctk::reexports::calloop::{generic::Generic, Interest, Mode, PostAction};
+use sctk::reexports::protocols::ext::data_control::v1::client::{
+    ext_data_control_manager_v1::ExtDataControlManagerV1,
+    ext_data_control_source_v1::ExtDataControlSourceV1,
+};
+use sctk::reexports::protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
+};
+use tracing::warn;
+use wayland_backend::client::ObjectId;
+use wayland_client::{
+    protocol::{wl_data_device_manager::WlDataDeviceManager, wl_data_source::WlDataSource},
+    Proxy, QueueHandle,
+};
+
+use super::{
+    cliphandler::IMAGE,
+    selection::{receive_into_pipe, set_nonblocking, TEXT_MIMES},
+    SelectionSource, SurfaceId, WgpuLayerShellState,
+};
+use crate::application::WPEvent;
+
+/// The MIME types a selection is offered in, with the data for each.
+pub type SelectionOffers = Vec<(String, Vec<u8>)>;
+
+/// Offers `text` in all the text MIME types.
+pub fn text_offers(text: &str) -> SelectionOffers {
+    TEXT_MIMES
+        .iter()
+        .map(|mime| (mime.to_string(), text.as_bytes().to_vec()))
+        .collect()
+}
+
+pub(crate) enum DataSource {
+    Ext(ExtDataControlSourceV1),
+    Wlr(ZwlrDataControlSourceV1),
+    /// Only for the clipboard, while a surface has keyboard focus.
+    Data(WlDataSource),
+}
+
+impl DataSource {
+    fn id(&self) -> ObjectId {
+        match self {
+            DataSource::Ext(source) => source.id(),
+            DataSource::Wlr(source) => source.id(),
+            DataSource::Data(source) => source.id(),
+        }
+    }
+
+    fn destroy(&self) {
+        match self {
+            DataSource::Ext(source) => source.destroy(),
+            DataSource::Wlr(source) => source.destroy(),
+            DataSource::Data(source) => source.destroy(),
+        }
+    }
+}
+
+/// Creates the data sources of one of the protocols a selection can be set through.
+trait SourceManager {
+    type Source;
+
+    fn create_source(
+        &self,
+        qh: &QueueHandle<WgpuLayerShellState>,
+        target: SelectionSource,
+    ) -> Self::Source;
+
+    fn offer(source: &Self::Source, mime: String);
+
+    /// A source offering the MIME types of `offers`, `None` to clear the selection.
+    fn source_for(
+        &self,
+        qh: &QueueHandle<WgpuLayerShellState>,
+        target: SelectionSource,
+        offers: &SelectionOffers,
+    ) -> Option<Self::Source> {
+        (!offers.is_empty()).then(|| {
+            let source = self.create_source(qh, target);
+            for (mime, _) in offers {
+                Self::offer(&source, mime.clone());
+            }
+            source
+        })
+    }
+}
+
+impl SourceManager for ExtDataControlManagerV1 {
+    type Source = ExtDataControlSourceV1;
+
+    fn create_source(
+        &self,
+        qh: &QueueHandle<WgpuLayerShellState>,
+        target: SelectionSource,
+    ) -> Self::Source {
+        self.create_data_source(qh, target)
+    }
+
+    fn offer(source: &Self::Source, mime: String) {
+        source.offer(mime);
+    }
+}
+
+impl SourceManager for ZwlrDataControlManagerV1 {
+    type Source = ZwlrDataControlSourceV1;
+
+    fn create_source(
+        &self,
+        qh: &QueueHandle<WgpuLayerShellState>,
+        target: SelectionSource,
+    ) -> Self::Source {
+        self.create_data_source(qh, target)
+    }
+
+    fn offer(source: &Self::Source, mime: String) {
+        source.offer(mime);
+    }
+}
+
+impl SourceManager for WlDataDeviceManager {
+    type Source = WlDataSource;
+
+    fn create_source(
+        &self,
+        qh: &QueueHandle<WgpuLayerShellState>,
+        target: SelectionSource,
+    ) -> Self::Source {
+        self.create_data_source(qh, target)
+    }
+
+    fn offer(source: &Self::Source, mime: String) {
+        source.offer(mime);
+    }
+}
+
+/// A selection set by the apps, served until another client replaces it.
+pub(crate) struct OwnedSelection {
+    source: DataSource,
+    offers: Arc<SelectionOffers>,
+}
+
+impl WgpuLayerShellState {
+    /// Makes `offers` the current clipboard or primary selection.
+    ///
+    /// Set on the seat of the last key press or pointer button. Data control is used when
+    /// available, otherwise the clipboard is set through the seat while it has keyboard focus
+    /// on a surface. No offers clear the selection.
+    /// [`WPEvent::SelectionCancelled`] is sent once another client replaces it.
+    pub fn set_selection(&mut self, target: SelectionSource, offers: SelectionOffers) {
+        let qh = self.queue_handle.as_ref();
+        let primary = target == SelectionSource::Primary;
+        // Data control takes any seat before the first input.
+        let Some(per_seat) = self
+            .input_seat
+            .as_ref()
+            .and_then(|seat| self.seat_map.get(seat))
+            .or_else(|| self.seat_map.values().next())
+        else {
+            warn!("setting the {:?} selection needs a seat", target);
+            return;
+        };
+        let source = if let (Some(manager), Some(device)) =
+            (&self.ext_data_manager, &per_seat.ext_data_device)
+        {
+            let source = manager.source_for(qh, target, &offers);
+            if primary {
+                device.set_primary_selection(source.as_ref());
+            } else {
+                device.set_selection(source.as_ref());
+            }
+            source.map(DataSource::Ext)
+        } else if let (Some(manager), Some(device)) = (&self.data_manager, &per_seat.data_device) {
+            if primary && device.version() < 2 {
+                warn!("the compositor does not support setting the primary selection");
+                return;
+            }
+            let source = manager.source_for(qh, target, &offers);
+            if primary {
+                device.set_primary_selection(source.as_ref());
+            } else {
+                device.set_selection(source.as_ref());
+            }
+            source.map(DataSource::Wlr)
+        } else if let (Some(manager), Some(device), false) =
+            (&self.data_device_manager, &per_seat.wl_data_device, primary)
+        {
+            if per_seat.keyboard_focus.is_none() {
+                warn!("setting the clipboard without data control needs keyboard focus");
+                return;
+            }
+            let source = manager.source_for(qh, target, &offers);
+            device.set_selection(source.as_ref(), per_seat.serial);
+            source.map(DataSource::Data)
+        } else {
+            warn!("setting the {:?} selection needs data control", target);
+            return;
+        };
+
+        if target == SelectionSource::Clipboard {
+            self.record_own_selection(&offers);
+        }
+        let owned = source.map(|source| OwnedSelection {
+            source,
+            offers: Arc::new(offers),
+        });
+        let replaced = match owned {
+            Some(owned) => self.owned_selections.insert(target, owned),
+            None => self.owned_selections.remove(&target),
+        };
+        if let Some(replaced) = replaced {
+            replaced.source.destroy();
+        }
+    }
+
+    /// Whether the selection is one the apps set, reading it would wait on ourselves.
+    pub(crate) fn owns_selection(&self, target: SelectionSource) -> bool {
+        self.owned_selections.contains_key(&target)
+    }
+
+    /// Writes the data offered as `mime` into `fd` on the event loop, as the reader may be
+    /// slow.
+    pub(crate) fn serve_selection(
+        &self,
+        target: SelectionSource,
+        source: ObjectId,
+        mime: String,
+        fd: OwnedFd,
+    ) {
+        let Some(owned) = self
+            .owned_selections
+            .get(&target)
+            .filter(|owned| owned.source.id() == source)
+        else {
+            return;
+        };
+        let Some(index) = owned
+            .offers
+            .iter()
+            .position(|(offered, _)| *offered == mime)
+        else {
+            warn!(
+                "{:?} selection was requested as {}, not offered",
+                target, mime
+            );
+            return;
+        };
+        let file = File::from(fd);
+        if let Err(e) = set_nonblocking(&file) {
+            warn!("could not send the {:?} selection: {}", target, e);
+            return;
+        }
+        let offers = Arc::clone(&owned.offers);
+        let mut written = 0;
+        let inserted = self.loop_handle.insert_source(
+            Generic::new(file, Interest::WRITE, Mode::Level),
+            move |_, file, _| loop {
+                let data = &offers[index].1;
+                if written == data.len() {
+                    return Ok(PostAction::Remove);
+                }
+                match (&**file).write(&data[written..]) {
+                    Ok(count) => written += count,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(PostAction::Continue),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        warn!("could not send the {:?} selection: {}", target, e);
+                        return Ok(PostAction::Remove);
+                    }
+                }
+            },
+        );
+        if let Err(e) = inserted {
+            warn!("could not send the {:?} selection: {}", target, e.error);
+        }
+    }
+
+    /// Another client replaced the selection the apps set.
+    pub(crate) fn selection_cancelled(&mut self, target: SelectionSource, source: ObjectId) {
+        let is_current = |owned: &OwnedSelection| owned.source.id() == source;
+        if !self.owned_selections.get(&target).is_some_and(is_current) {
+            return;
+        }
+        if let Some(owned) = self.owned_selections.remove(&target) {
+            owned.source.destroy();
+        }
+        self.emit(WPEvent::SelectionCancelled(target));
+    }
+
+    /// Pastes the clipboard text into the surface, once it has been read.
+    pub(crate) fn paste(&mut self, id: SurfaceId) {
+        let push_paste = move |state: &mut Self, text: String| {
+            if let Some(surface) = state.surfaces.get_mut(&id) {
+                surface
+                    .egui_state
+                    .input()
+                    .events
+                    .push(egui::Event::Paste(text));
+                surface.egui_state.context().request_repaint();
+            }
+        };
+
+        // Our own selection is at hand, reading it through the compositor would block.
+        if let Some(owned) = self.owned_selections.get(&SelectionSource::Clipboard) {
+            let text = owned
+                .offers
+                .iter()
+                .find(|(mime, _)| TEXT_MIMES.contains(&mime.as_str()))
+                .and_then(|(_, data)| String::from_utf8(data.clone()).ok());
+            if let Some(text) = text {
+                push_paste(self, text);
+            }
+            return;
+        }
+
+        let Some(offer) = self.selection_offers.get(&SelectionSource::Clipboard) else {
+            return;
+        };
+        let mimes = offer.mime_types();
+        let Some(mime) = TEXT_MIMES
+            .iter()
+            .find(|text| mimes.iter().any(|m| m == *text))
+        else {
+            return;
+        };
+        let Some(reader) = receive_into_pipe(mime.to_string(), |mime, fd| offer.receive(mime, fd))
+        else {
+            return;
+        };
+        self.read_pipe(reader, SelectionSource::Clipboard, move |state, data| {
+            match String::from_utf8(data) {
+                Ok(text) => push_paste(state, text),
+                Err(_) => warn!("the clipboard text is not valid UTF-8"),
+            }
+        });
+    }
+
+    /// Puts what egui copied on the clipboard.
+    pub(crate) fn handle_output_commands(&mut self, commands: Vec<OutputCommand>) {
+        for command in commands {
+            match command {
+                OutputCommand::CopyText(text) => {
+                    self.set_selection(SelectionSource::Clipboard, text_offers(&text));
+                }
+                OutputCommand::CopyImage(image) => match encode_png(&image) {
+                    Ok(png) => {
+                        self.set_selection(SelectionSource::Clipboard, vec![(IMAGE.into(), png)])
+                    }
+                    Err(e) => warn!("could not encode the copied image: {}", e),
+                },
+                OutputCommand::OpenUrl(_) => {}
+            }
+        }
+    }
+}
+
+fn encode_png(image: &ColorImage) -> Result<Vec<u8>, png::EncodingError> {
+    let [width, height] = image.size;
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    // Color32 is premultiplied, PNG stores straight alpha.
+    let pixels: Vec<u8> = image
+        .pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+        .collect();
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(png)
+}
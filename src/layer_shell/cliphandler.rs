@@ -1,10 +1,3 @@
-use std::fs::File;
-use std::io::pipe;
-use std::io::Read;
-use std::io::Write;
-use std::os::fd::AsFd;
-use std::os::fd::IntoRawFd;
-
 use sctk::reexports::protocols::ext::data_control::v1::client::ext_data_control_device_v1;
 use sctk::reexports::protocols::ext::data_control::v1::client::ext_data_control_manager_v1;
 use sctk::reexports::protocols::ext::data_control::v1::client::ext_data_control_offer_v1;
@@ -20,30 +13,50 @@ use sctk::reexports::protocols_wlr::data_control::v1::client::{
     zwlr_data_control_source_v1,
 };
 use tracing::warn;
-use wayland_client::protocol::wl_data_device_manager;
-use wayland_client::{
-    event_created_child,
-    protocol::{wl_registry, wl_seat},
-    Connection, Dispatch, Proxy,
-};
+use wayland_client::protocol::{wl_data_device, wl_data_device_manager, wl_data_offer, wl_data_source};
+use wayland_backend::client::ObjectId;
+use wayland_client::{event_created_child, protocol::wl_registry, Connection, Dispatch, Proxy};
 
-use crate::application::WPEvent;
-use crate::layer_shell::WgpuLayerShellState;
-
-#[derive(Debug)]
-pub enum WlListenType {
-    ListenOnSelect,
-    ListenOnCopy,
-}
+use crate::layer_shell::selection::{OfferMimes, SelectionOffer, SelectionSource};
+use crate::layer_shell::{PerSeat, WgpuLayerShellState};
 
 pub(crate) const TEXT: &str = "text/plain;charset=utf-8";
 pub(crate) const IMAGE: &str = "image/png";
 
 impl WgpuLayerShellState {
-    fn is_text(&self) -> bool {
-        !self.mime_types.is_empty()
-            && self.mime_types.contains(&TEXT.to_string())
-            && !self.mime_types.contains(&IMAGE.to_string())
+    /// Creates the devices of the bound selection managers for every seat, once both exist.
+    ///
+    /// The devices carry the id of their seat.
+    pub(crate) fn create_data_devices(&mut self) {
+        let qh = self.queue_handle.as_ref();
+        for (id, per_seat) in self.seat_map.iter_mut() {
+            let seat = &per_seat.seat;
+            if !per_seat.has_data_control_device() {
+                if let Some(manager) = &self.ext_data_manager {
+                    per_seat.ext_data_device = Some(manager.get_data_device(seat, qh, id.clone()));
+                } else if let Some(manager) = &self.data_manager {
+                    per_seat.data_device = Some(manager.get_data_device(seat, qh, id.clone()));
+                }
+            }
+            if per_seat.primary_selection_device.is_none() {
+                if let Some(manager) = &self.primary_selection_manager {
+                    per_seat.primary_selection_device =
+                        Some(manager.get_device(seat, qh, id.clone()));
+                }
+            }
+            if per_seat.wl_data_device.is_none() {
+                if let Some(manager) = &self.data_device_manager {
+                    per_seat.wl_data_device = Some(manager.get_data_device(seat, qh, id.clone()));
+                }
+            }
+        }
+    }
+
+    /// Whether the seat's selections come through data control.
+    fn has_data_control_device(&self, seat: &ObjectId) -> bool {
+        self.seat_map
+            .get(seat)
+            .is_some_and(PerSeat::has_data_control_device)
     }
 }
 
@@ -59,132 +72,75 @@ impl Dispatch<ext_data_control_manager_v1::ExtDataControlManagerV1, ()> for Wgpu
     }
 }
 
-impl Dispatch<ext_data_control_device_v1::ExtDataControlDeviceV1, ()> for WgpuLayerShellState {
+impl Dispatch<ext_data_control_device_v1::ExtDataControlDeviceV1, ObjectId>
+    for WgpuLayerShellState
+{
     fn event(
         state: &mut Self,
         _proxy: &ext_data_control_device_v1::ExtDataControlDeviceV1,
         event: <ext_data_control_device_v1::ExtDataControlDeviceV1 as Proxy>::Event,
-        _data: &(),
+        seat: &ObjectId,
         _conn: &Connection,
-        qh: &wayland_client::QueueHandle<Self>,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
-            ext_data_control_device_v1::Event::DataOffer { id } => {
-                if state.copy_data.is_some() {
-                    return;
-                }
-                if let WlListenType::ListenOnSelect = state.listentype {
-                    let (read, write) = pipe().unwrap();
-                    state.current_type = Some(TEXT.to_string());
-                    id.receive(TEXT.to_string(), write.as_fd());
-                    drop(write);
-                    let _ = state.ev.send(WPEvent::Fd(read));
-                    warn!("ext_data_control_device_v1::Event::DataOffer");
-                }
-            }
-            ext_data_control_device_v1::Event::Finished => {
-                let source = state
-                    .data_manager
-                    .as_ref()
-                    .unwrap()
-                    .create_data_source(qh, ());
-                state
-                    .data_device
-                    .as_ref()
-                    .unwrap()
-                    .set_selection(Some(&source));
+            ext_data_control_device_v1::Event::Selection { id } => {
+                let offer = id.map(SelectionOffer::Ext);
+                state.set_selection_offer(SelectionSource::Clipboard, seat, offer);
             }
             ext_data_control_device_v1::Event::PrimarySelection { id } => {
-                if let Some(offer) = id {
-                    let (read, write) = std::io::pipe().unwrap();
-                    offer.receive(TEXT.to_string(), write.as_fd());
-                    warn!("ext_data_control_device_v1 PrimarySelection");
-                    let _ = state.ev.send(WPEvent::Fd(read));
-                    offer.destroy();
-                }
+                let offer = id.map(SelectionOffer::Ext);
+                state.set_selection_offer(SelectionSource::Primary, seat, offer);
             }
-            ext_data_control_device_v1::Event::Selection { id } => {
-                let Some(offer) = id else {
-                    return;
-                };
-
-                warn!("ext_data_control_device_v1::Event::Selection");
-                // if is copying, not run this
-                if state.copy_data.is_some() {
-                    return;
-                }
-                // TODO: how can I handle the mimetype?
-                let select_mimetype = |state: &WgpuLayerShellState| {
-                    if state.is_text() || state.mime_types.is_empty() {
-                        TEXT.to_string()
-                    } else {
-                        state.mime_types[0].clone()
-                    }
-                };
-                if let WlListenType::ListenOnCopy = state.listentype {
-                    // if priority is set
-                    let mimetype = if let Some(val) = &state.set_priority {
-                        val.iter()
-                            .find(|i| state.mime_types.contains(i))
-                            .cloned()
-                            .unwrap_or_else(|| select_mimetype(state))
-                    } else {
-                        select_mimetype(state)
-                    };
-                    state.current_type = Some(mimetype.clone());
-                    let (read, write) = pipe().unwrap();
-                    offer.receive(mimetype, write.as_fd());
-                    drop(write);
-                    state.pipereader = Some(read);
+            ext_data_control_device_v1::Event::Finished => {
+                if let Some(per_seat) = state.seat_map.get_mut(seat) {
+                    per_seat.ext_data_device = None;
                 }
             }
-            _ => {
-                log::info!("unhandled event: {event:?}");
-            }
+            _ => {}
         }
     }
     event_created_child!(WgpuLayerShellState, ext_data_control_device_v1::ExtDataControlDeviceV1, [
-        ext_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ext_data_control_offer_v1::ExtDataControlOfferV1, ()),
+        ext_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ext_data_control_offer_v1::ExtDataControlOfferV1, OfferMimes::default()),
     ]);
 }
 
-impl Dispatch<ext_data_control_source_v1::ExtDataControlSourceV1, ()> for WgpuLayerShellState {
+impl Dispatch<ext_data_control_source_v1::ExtDataControlSourceV1, SelectionSource>
+    for WgpuLayerShellState
+{
     fn event(
         state: &mut Self,
-        _proxy: &ext_data_control_source_v1::ExtDataControlSourceV1,
+        proxy: &ext_data_control_source_v1::ExtDataControlSourceV1,
         event: <ext_data_control_source_v1::ExtDataControlSourceV1 as Proxy>::Event,
-        _data: &(),
+        target: &SelectionSource,
         _conn: &Connection,
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
             ext_data_control_source_v1::Event::Send { fd, mime_type } => {
-                let Some(data) = state.copy_data.as_ref() else {
-                    return;
-                };
-                // FIXME: how to handle the mime_type?
-                if mime_type == TEXT || mime_type == IMAGE {
-                    let mut f = File::from(fd);
-                    f.write_all(&data.to_vec()).unwrap();
-                }
+                state.serve_selection(*target, proxy.id(), mime_type, fd);
+            }
+            ext_data_control_source_v1::Event::Cancelled => {
+                state.selection_cancelled(*target, proxy.id())
             }
-            ext_data_control_source_v1::Event::Cancelled => state.copy_cancelled = true,
             _ => {}
         }
     }
 }
 
-impl Dispatch<ext_data_control_offer_v1::ExtDataControlOfferV1, ()> for WgpuLayerShellState {
+impl Dispatch<ext_data_control_offer_v1::ExtDataControlOfferV1, OfferMimes>
+    for WgpuLayerShellState
+{
     fn event(
-        state: &mut Self,
+        _state: &mut Self,
         _proxy: &ext_data_control_offer_v1::ExtDataControlOfferV1,
         event: <ext_data_control_offer_v1::ExtDataControlOfferV1 as Proxy>::Event,
-        _data: &(),
+        mimes: &OfferMimes,
         _conn: &Connection,
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         if let ext_data_control_offer_v1::Event::Offer { mime_type } = event {
-            state.mime_types.push(mime_type);
+            mimes.push(mime_type);
         }
     }
 }
@@ -222,34 +178,22 @@ impl Dispatch<wl_registry::WlRegistry, ()> for WgpuLayerShellState {
         {
             warn!(name = name, interface = interface);
             if interface == ZwpPrimarySelectionDeviceManagerV1::interface().name {
-                let mg = registry.bind::<ZwpPrimarySelectionDeviceManagerV1, _, _>(
-                    name,
-                    version,
-                    qh,
-                    (),
-                );
-                if state.zwp_data_dev.is_none() {
-                    if let Some(seat) = &state.seat {
-                        state.zwp_data_dev = Some(mg.get_device(&seat, &state.queue_handle, ()));
-                    }
-                }
+                state.primary_selection_manager =
+                    Some(registry.bind::<ZwpPrimarySelectionDeviceManagerV1, _, _>(
+                        name,
+                        version,
+                        qh,
+                        (),
+                    ));
             } else if interface == wl_data_device_manager::WlDataDeviceManager::interface().name {
-                registry.bind::<wl_data_device_manager::WlDataDeviceManager, _, _>(
-                    name,
-                    version,
-                    qh,
-                    (),
+                state.data_device_manager = Some(
+                    registry.bind::<wl_data_device_manager::WlDataDeviceManager, _, _>(
+                        name,
+                        version,
+                        qh,
+                        (),
+                    ),
                 );
-            } else if interface == wl_seat::WlSeat::interface().name {
-                warn!(interface, "found");
-                if state.seat.is_none() {
-                    state.seat =
-                        Some(registry.bind::<wl_seat::WlSeat, _, _>(name, version, qh, ()));
-                    let seat = state.seat.as_ref().unwrap();
-                    if let Some(mg) = &state.data_manager {
-                        state.data_device = Some(mg.get_data_device(seat, &state.queue_handle, ()));
-                    }
-                }
             } else if interface
                 == ext_data_control_manager_v1::ExtDataControlManagerV1::interface().name
             {
@@ -279,34 +223,81 @@ impl Dispatch<wl_registry::WlRegistry, ()> for WgpuLayerShellState {
             } else {
                 // tracing::warn!("registry ignored {} {}", interface, name)
             }
+            // Globals come in any order, devices need both their manager and a seat.
+            state.create_data_devices();
         }
     }
 }
 
-impl Dispatch<wl_seat::WlSeat, ()> for WgpuLayerShellState {
+impl Dispatch<wl_data_device_manager::WlDataDeviceManager, ()> for WgpuLayerShellState {
     fn event(
         state: &mut Self,
-        _proxy: &wl_seat::WlSeat,
-        event: <wl_seat::WlSeat as Proxy>::Event,
+        _proxy: &wl_data_device_manager::WlDataDeviceManager,
+        event: <wl_data_device_manager::WlDataDeviceManager as Proxy>::Event,
         _data: &(),
         _conn: &wayland_client::Connection,
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        if let wl_seat::Event::Name { name } = event {
-            state.seat_name = Some(name);
+    }
+}
+
+impl Dispatch<wl_data_device::WlDataDevice, ObjectId> for WgpuLayerShellState {
+    fn event(
+        state: &mut Self,
+        _proxy: &wl_data_device::WlDataDevice,
+        event: <wl_data_device::WlDataDevice as Proxy>::Event,
+        seat: &ObjectId,
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        // Only sent while focused, data control keeps track of the clipboard when there.
+        if let wl_data_device::Event::Selection { id } = event {
+            if state.has_data_control_device(seat) {
+                if let Some(offer) = id {
+                    offer.destroy();
+                }
+            } else {
+                let offer = id.map(SelectionOffer::Data);
+                state.set_selection_offer(SelectionSource::Clipboard, seat, offer);
+            }
         }
     }
+    event_created_child!(WgpuLayerShellState, wl_data_device::WlDataDevice, [
+        wl_data_device::EVT_DATA_OFFER_OPCODE => (wl_data_offer::WlDataOffer, OfferMimes::default()),
+    ]);
 }
 
-impl Dispatch<wl_data_device_manager::WlDataDeviceManager, ()> for WgpuLayerShellState {
+impl Dispatch<wl_data_offer::WlDataOffer, OfferMimes> for WgpuLayerShellState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_data_offer::WlDataOffer,
+        event: <wl_data_offer::WlDataOffer as Proxy>::Event,
+        mimes: &OfferMimes,
+        _conn: &wayland_client::Connection,
+        _qhandle: &wayland_client::QueueHandle<Self>,
+    ) {
+        if let wl_data_offer::Event::Offer { mime_type } = event {
+            mimes.push(mime_type);
+        }
+    }
+}
+
+impl Dispatch<wl_data_source::WlDataSource, SelectionSource> for WgpuLayerShellState {
     fn event(
         state: &mut Self,
-        _proxy: &wl_data_device_manager::WlDataDeviceManager,
-        event: <wl_data_device_manager::WlDataDeviceManager as Proxy>::Event,
-        _data: &(),
+        proxy: &wl_data_source::WlDataSource,
+        event: <wl_data_source::WlDataSource as Proxy>::Event,
+        target: &SelectionSource,
         _conn: &wayland_client::Connection,
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
+        match event {
+            wl_data_source::Event::Send { mime_type, fd } => {
+                state.serve_selection(*target, proxy.id(), mime_type, fd);
+            }
+            wl_data_source::Event::Cancelled => state.selection_cancelled(*target, proxy.id()),
+            _ => {}
+        }
     }
 }
 
@@ -322,57 +313,49 @@ impl Dispatch<ZwpPrimarySelectionDeviceManagerV1, ()> for WgpuLayerShellState {
     }
 }
 
-impl Dispatch<ZwpPrimarySelectionDeviceV1, ()> for WgpuLayerShellState {
+impl Dispatch<ZwpPrimarySelectionDeviceV1, ObjectId> for WgpuLayerShellState {
     fn event(
         state: &mut Self,
         _proxy: &ZwpPrimarySelectionDeviceV1,
         event: <ZwpPrimarySelectionDeviceV1 as Proxy>::Event,
-        _data: &(),
+        seat: &ObjectId,
         _conn: &wayland_client::Connection,
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         use zwp_primary_selection_device_v1::Event;
-        match event {
-            Event::Selection { id } => {
-                warn!("selection");
-                // let Some(offer) = id else {
-                //     return;
-                // };
-                // let (read, write) = std::io::pipe().unwrap();
-                // offer.receive(TEXT.to_string(), write.as_fd());
-                // warn!("ZwpPrimarySelectionDeviceV1 Selection");
-                // let _ = state.ev.send(WPEvent::Fd(read));
-                // offer.destroy();
-            }
-            Event::DataOffer { offer } => {
-                let (read, write) = std::io::pipe().unwrap();
-                offer.receive(TEXT.to_string(), write.as_fd());
-                warn!("ZwpPrimarySelectionDeviceV1 DataOffer");
-                let _ = state.ev.send(WPEvent::Fd(read));
-                offer.destroy();
+        // Data control sees the primary selection too, without needing focus.
+        if let Event::Selection { id } = event {
+            if state.has_data_control_device(seat) {
+                if let Some(offer) = id {
+                    offer.destroy();
+                }
+            } else {
+                let offer = id.map(SelectionOffer::Primary);
+                state.set_selection_offer(SelectionSource::Primary, seat, offer);
             }
-            _ => {}
         }
     }
     event_created_child!(WgpuLayerShellState, ZwpPrimarySelectionDeviceV1, [
-        zwp_primary_selection_device_v1::EVT_DATA_OFFER_OPCODE => (zwp_primary_selection_offer_v1::ZwpPrimarySelectionOfferV1, ()),
-        zwp_primary_selection_device_v1::EVT_SELECTION_OPCODE => (zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1, ()),
+        zwp_primary_selection_device_v1::EVT_DATA_OFFER_OPCODE => (zwp_primary_selection_offer_v1::ZwpPrimarySelectionOfferV1, OfferMimes::default()),
     ]);
 }
 
 use crate::layer_shell::cliphandler::zwp_primary_selection_offer_v1::ZwpPrimarySelectionOfferV1;
 
-impl Dispatch<zwp_primary_selection_offer_v1::ZwpPrimarySelectionOfferV1, ()>
+impl Dispatch<zwp_primary_selection_offer_v1::ZwpPrimarySelectionOfferV1, OfferMimes>
     for WgpuLayerShellState
 {
     fn event(
         _state: &mut Self,
         _proxy: &ZwpPrimarySelectionOfferV1,
-        _event: <ZwpPrimarySelectionOfferV1 as Proxy>::Event,
-        _data: &(),
+        event: <ZwpPrimarySelectionOfferV1 as Proxy>::Event,
+        mimes: &OfferMimes,
         _conn: &wayland_client::Connection,
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
+        if let zwp_primary_selection_offer_v1::Event::Offer { mime_type } = event {
+            mimes.push(mime_type);
+        }
     }
 }
 
@@ -388,71 +371,75 @@ impl Dispatch<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1, ()> for Wg
     }
 }
 
-impl Dispatch<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, ()> for WgpuLayerShellState {
+impl Dispatch<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, ObjectId>
+    for WgpuLayerShellState
+{
     fn event(
         state: &mut Self,
         _proxy: &zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
         event: <zwlr_data_control_device_v1::ZwlrDataControlDeviceV1 as Proxy>::Event,
-        _data: &(),
+        seat: &ObjectId,
         _conn: &Connection,
-        qh: &wayland_client::QueueHandle<Self>,
+        _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
+            zwlr_data_control_device_v1::Event::Selection { id } => {
+                let offer = id.map(SelectionOffer::Wlr);
+                state.set_selection_offer(SelectionSource::Clipboard, seat, offer);
+            }
             zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
-                let Some(offer) = id else {
-                    return;
-                };
-                let (read, write) = std::io::pipe().unwrap();
-                offer.receive(TEXT.to_string(), write.as_fd());
-                warn!("ZwlrDataControlDeviceV1 PrimarySelection");
-                let _ = state.ev.send(WPEvent::Fd(read));
-                offer.destroy();
+                let offer = id.map(SelectionOffer::Wlr);
+                state.set_selection_offer(SelectionSource::Primary, seat, offer);
+            }
+            zwlr_data_control_device_v1::Event::Finished => {
+                if let Some(per_seat) = state.seat_map.get_mut(seat) {
+                    per_seat.data_device = None;
+                }
             }
             _ => {}
         }
     }
     event_created_child!(WgpuLayerShellState, zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, [
-        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (zwlr_data_control_offer_v1::ZwlrDataControlOfferV1, ())
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (zwlr_data_control_offer_v1::ZwlrDataControlOfferV1, OfferMimes::default())
     ]);
 }
 
-impl Dispatch<zwlr_data_control_source_v1::ZwlrDataControlSourceV1, ()> for WgpuLayerShellState {
+impl Dispatch<zwlr_data_control_source_v1::ZwlrDataControlSourceV1, SelectionSource>
+    for WgpuLayerShellState
+{
     fn event(
         state: &mut Self,
-        _proxy: &zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
+        proxy: &zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
         event: <zwlr_data_control_source_v1::ZwlrDataControlSourceV1 as Proxy>::Event,
-        _data: &(),
+        target: &SelectionSource,
         _conn: &Connection,
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         match event {
             zwlr_data_control_source_v1::Event::Send { fd, mime_type } => {
-                let Some(data) = state.copy_data.as_ref() else {
-                    return;
-                };
-                // FIXME: how to handle the mime_type?
-                if mime_type == TEXT || mime_type == IMAGE {
-                    let mut f = File::from(fd);
-                    f.write_all(&data.to_vec()).unwrap();
-                }
+                state.serve_selection(*target, proxy.id(), mime_type, fd);
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                state.selection_cancelled(*target, proxy.id())
             }
-            zwlr_data_control_source_v1::Event::Cancelled => state.copy_cancelled = true,
             _ => {}
         }
     }
 }
 
-impl Dispatch<zwlr_data_control_offer_v1::ZwlrDataControlOfferV1, ()> for WgpuLayerShellState {
+impl Dispatch<zwlr_data_control_offer_v1::ZwlrDataControlOfferV1, OfferMimes>
+    for WgpuLayerShellState
+{
     fn event(
-        state: &mut Self,
+        _state: &mut Self,
         _proxy: &zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
         event: <zwlr_data_control_offer_v1::ZwlrDataControlOfferV1 as Proxy>::Event,
-        _data: &(),
+        mimes: &OfferMimes,
         _conn: &Connection,
         _qhandle: &wayland_client::QueueHandle<Self>,
     ) {
         if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
-            state.mime_types.push(mime_type);
+            mimes.push(mime_type);
         }
     }
 }
@@ -0,0 +1,69 @@
+use sctk::reexports::protocols::wp::{
+    fractional_scale::v1::client::{
+        wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+    },
+    viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+};
+use sctk::shell::WaylandSurface;
+use wayland_client::{delegate_noop, Connection, Dispatch, QueueHandle};
+
+use super::{SurfaceId, WgpuLayerShellState};
+
+/// wp_fractional_scale_v1 reports scales as multiples of 1/120.
+const SCALE_DENOMINATOR: f64 = 120.;
+
+delegate_noop!(WgpuLayerShellState: ignore WpFractionalScaleManagerV1);
+delegate_noop!(WgpuLayerShellState: ignore WpViewporter);
+delegate_noop!(WgpuLayerShellState: ignore WpViewport);
+
+impl Dispatch<WpFractionalScaleV1, SurfaceId> for WgpuLayerShellState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: <WpFractionalScaleV1 as wayland_client::Proxy>::Event,
+        id: &SurfaceId,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            state.set_surface_scale(*id, scale as f64 / SCALE_DENOMINATOR);
+        }
+    }
+}
+
+impl WgpuLayerShellState {
+    /// Attaches a viewport, and a fractional scale object if both globals are present.
+    ///
+    /// Without a viewport only the integer `wl_surface` buffer scale is used.
+    pub(crate) fn attach_scale_objects(&mut self, id: SurfaceId) {
+        let (Some(surface), Some(viewporter)) = (self.surfaces.get_mut(&id), &self.viewporter)
+        else {
+            return;
+        };
+        surface.release_scale_objects();
+        let wl_surface = surface.role.wl_surface();
+        surface.viewport = Some(viewporter.get_viewport(wl_surface, &self.queue_handle, ()));
+        surface.fractional_scale = self
+            .fractional_scale_manager
+            .as_ref()
+            .map(|m| m.get_fractional_scale(wl_surface, &self.queue_handle, id));
+    }
+
+    /// Applies a new scale to a surface and resizes its buffer accordingly.
+    pub(crate) fn set_surface_scale(&mut self, id: SurfaceId, scale: f64) {
+        let Some(wgpu_state) = self.wgpu_state.as_ref() else {
+            return;
+        };
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return;
+        };
+        if surface.scale_factor == scale {
+            return;
+        }
+        surface.scale_factor = scale;
+        if surface.is_configured {
+            surface.apply_size(&wgpu_state.device);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! Remembers the clipboard, for apps showing a clipboard history.
+
+use std::{
+    collections::VecDeque,
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::{
+    clipboard::text_offers,
+    selection::{is_text_mime, pick_mime},
+    SelectionSource, WgpuLayerShellState,
+};
+
+/// Offered by password managers along with the passwords they copy, which are not recorded.
+const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+
+/// A selection that was on the clipboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// All MIME types the selection was offered in.
+    pub mimes: Vec<String>,
+    /// The MIME type `data` was read in.
+    pub mime: String,
+    pub data: Vec<u8>,
+    /// When the selection was last made.
+    pub time: SystemTime,
+}
+
+impl HistoryEntry {
+    /// The entry as text, if it was read as text and is valid UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        is_text_mime(&self.mime)
+            .then(|| std::str::from_utf8(&self.data).ok())
+            .flatten()
+    }
+}
+
+/// The latest clipboard selections, newest first.
+///
+/// Making a selection again moves its entry to the front. Secrets copied from password
+/// managers are left out. Persistent histories are saved after every change, on a thread
+/// of their own.
+#[derive(Debug)]
+pub struct ClipboardHistory {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+    saver: Option<Saver>,
+}
+
+/// The thread saving a persistent history.
+#[derive(Debug)]
+struct Saver {
+    /// Sends it the encoded entries.
+    sender: flume::Sender<Vec<u8>>,
+    thread: JoinHandle<()>,
+}
+
+impl Drop for ClipboardHistory {
+    /// Waits for the thread to save what is still queued.
+    fn drop(&mut self) {
+        if let Some(Saver { sender, thread }) = self.saver.take() {
+            drop(sender);
+            if thread.join().is_err() {
+                warn!("saving the clipboard history panicked");
+            }
+        }
+    }
+}
+
+impl ClipboardHistory {
+    /// A history kept in memory only.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            saver: None,
+        }
+    }
+
+    /// A history saved to [`ClipboardHistory::default_path`], loading what was saved there.
+    pub fn persistent(capacity: usize) -> Self {
+        match Self::default_path() {
+            Some(path) => Self::with_path(capacity, path),
+            None => {
+                warn!("neither XDG_STATE_HOME nor HOME is set, the clipboard history is not saved");
+                Self::new(capacity)
+            }
+        }
+    }
+
+    /// A history saved to `path`, loading what was saved there.
+    pub fn with_path(capacity: usize, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut entries = match load(&path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!(
+                        "could not load the clipboard history from {:?}: {}",
+                        path, e
+                    );
+                }
+                VecDeque::new()
+            }
+        };
+        entries.truncate(capacity);
+        Self {
+            entries,
+            capacity,
+            saver: spawn_saver(path),
+        }
+    }
+
+    /// `$XDG_STATE_HOME/wpopup/clipboard-history`, `$XDG_STATE_HOME` defaulting to
+    /// `~/.local/state`.
+    pub fn default_path() -> Option<PathBuf> {
+        let state_home = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+        Some(state_home.join("wpopup").join("clipboard-history"))
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&HistoryEntry> {
+        self.entries.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Text entries containing `query`, ignoring case, with their index.
+    pub fn search<'a>(
+        &'a self,
+        query: &'a str,
+    ) -> impl Iterator<Item = (usize, &'a HistoryEntry)> + 'a {
+        let query = query.to_lowercase();
+        self.entries.iter().enumerate().filter(move |(_, entry)| {
+            entry
+                .text()
+                .is_some_and(|text| text.to_lowercase().contains(&query))
+        })
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<HistoryEntry> {
+        let entry = self.entries.remove(index)?;
+        self.save();
+        Some(entry)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.save();
+    }
+
+    /// Adds the selection at the front, dropping the oldest entry when full.
+    fn push(&mut self, mimes: Vec<String>, mime: String, data: Vec<u8>) {
+        if data.is_empty() || self.capacity == 0 {
+            return;
+        }
+        if mimes.iter().any(|mime| mime == PASSWORD_MANAGER_HINT) {
+            return;
+        }
+        // Text restored from the history is offered in other MIME types than it was read in.
+        self.entries.retain(|entry| entry.data != data);
+        self.entries.push_front(HistoryEntry {
+            mimes,
+            mime,
+            data,
+            time: SystemTime::now(),
+        });
+        self.entries.truncate(self.capacity);
+        self.save();
+    }
+
+    fn save(&self) {
+        let Some(saver) = &self.saver else {
+            return;
+        };
+        match encode(&self.entries) {
+            Ok(bytes) => {
+                let _ = saver.sender.send(bytes);
+            }
+            Err(e) => warn!("could not encode the clipboard history: {}", e),
+        }
+    }
+}
+
+fn load(path: &Path) -> io::Result<VecDeque<HistoryEntry>> {
+    let bytes = fs::read(path)?;
+    let (entries, _) = bincode::serde::decode_from_slice(&bytes, bincode::config::standard())
+        .map_err(io::Error::other)?;
+    Ok(entries)
+}
+
+fn encode(entries: &VecDeque<HistoryEntry>) -> io::Result<Vec<u8>> {
+    bincode::serde::encode_to_vec(entries, bincode::config::standard()).map_err(io::Error::other)
+}
+
+/// Saves the histories sent to it to `path`, skipping those replaced while saving.
+fn spawn_saver(path: PathBuf) -> Option<Saver> {
+    let (sx, rx) = flume::unbounded::<Vec<u8>>();
+    let spawned = thread::Builder::new()
+        .name("wpopup-history".to_owned())
+        .spawn(move || {
+            while let Ok(bytes) = rx.recv() {
+                let bytes = rx.drain().last().unwrap_or(bytes);
+                if let Err(e) = save(&path, &bytes) {
+                    warn!("could not save the clipboard history to {:?}: {}", path, e);
+                }
+            }
+        });
+    match spawned {
+        Ok(thread) => Some(Saver { sender: sx, thread }),
+        Err(e) => {
+            warn!("could not start saving the clipboard history: {}", e);
+            None
+        }
+    }
+}
+
+/// Writes next to `path` first, a crash while saving keeps the old history.
+///
+/// Only the user can read the file, the clipboard may have held passwords.
+fn save(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    // The mode only applies to new files, a leftover one may be readable by others.
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)?
+        .write_all(bytes)?;
+    fs::rename(tmp, path)
+}
+
+impl WgpuLayerShellState {
+    /// Starts recording the clipboard into `history`, or stops with `None`.
+    pub fn set_clipboard_history(&mut self, history: Option<ClipboardHistory>) {
+        self.clipboard_history = history;
+    }
+
+    pub fn clipboard_history(&self) -> Option<&ClipboardHistory> {
+        self.clipboard_history.as_ref()
+    }
+
+    pub fn clipboard_history_mut(&mut self) -> Option<&mut ClipboardHistory> {
+        self.clipboard_history.as_mut()
+    }
+
+    /// Makes the history entry at `index` the clipboard again.
+    pub fn restore_history_entry(&mut self, index: usize) {
+        let Some(entry) = self.clipboard_history.as_ref().and_then(|h| h.get(index)) else {
+            return;
+        };
+        let offers = match entry.text() {
+            Some(text) => text_offers(text),
+            None => vec![(entry.mime.clone(), entry.data.clone())],
+        };
+        self.set_selection(SelectionSource::Clipboard, offers);
+    }
+
+    /// Records a clipboard selection read in `mime`.
+    pub(crate) fn record_history(&mut self, mimes: Vec<String>, mime: String, data: Vec<u8>) {
+        if let Some(history) = &mut self.clipboard_history {
+            history.push(mimes, mime, data);
+        }
+    }
+
+    /// Records a clipboard selection set by the apps, in the MIME type a read would pick.
+    pub(crate) fn record_own_selection(&mut self, offers: &[(String, Vec<u8>)]) {
+        if self.clipboard_history.is_none() {
+            return;
+        }
+        let mimes: Vec<String> = offers.iter().map(|(mime, _)| mime.clone()).collect();
+        let Some(mime) = pick_mime(&mimes, &self.mime_priority) else {
+            return;
+        };
+        if let Some((_, data)) = offers.iter().find(|(offered, _)| *offered == mime) {
+            let data = data.clone();
+            self.record_history(mimes, mime, data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    const TEXT_MIME: &str = "text/plain;charset=utf-8";
+
+    fn push_text(history: &mut ClipboardHistory, text: &str) {
+        history.push(vec![TEXT_MIME.into()], TEXT_MIME.into(), text.into());
+    }
+
+    fn texts(history: &ClipboardHistory) -> Vec<&str> {
+        history.entries().filter_map(HistoryEntry::text).collect()
+    }
+
+    #[test]
+    fn moves_repeated_selection_to_front() {
+        let mut history = ClipboardHistory::new(5);
+        push_text(&mut history, "a");
+        push_text(&mut history, "b");
+        push_text(&mut history, "a");
+        assert_eq!(texts(&history), ["a", "b"]);
+    }
+
+    #[test]
+    fn drops_oldest_past_capacity() {
+        let mut history = ClipboardHistory::new(2);
+        for text in ["a", "b", "c"] {
+            push_text(&mut history, text);
+        }
+        assert_eq!(texts(&history), ["c", "b"]);
+    }
+
+    #[test]
+    fn skips_password_manager_secrets() {
+        let mut history = ClipboardHistory::new(2);
+        let mimes = vec![TEXT_MIME.into(), PASSWORD_MANAGER_HINT.into()];
+        history.push(mimes, TEXT_MIME.into(), b"hunter2".to_vec());
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn loads_what_was_saved() {
+        let dir = env::temp_dir().join(format!("wpopup-history-{}", std::process::id()));
+        let path = dir.join("clipboard-history");
+        let mut history = ClipboardHistory::new(3);
+        push_text(&mut history, "a");
+        history.push(vec!["image/png".into()], "image/png".into(), vec![1, 2, 3]);
+
+        save(&path, &encode(&history.entries).unwrap()).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let loaded = load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(loaded.len(), 2);
+        for (loaded, saved) in loaded.iter().zip(history.entries()) {
+            assert_eq!(loaded.mimes, saved.mimes);
+            assert_eq!(loaded.mime, saved.mime);
+            assert_eq!(loaded.data, saved.data);
+            assert_eq!(loaded.time, saved.time);
+        }
+    }
+    #[test]
+    fn saves_queued_changes_on_drop() {
+        let dir = env::temp_dir().join(format!("wpopup-saver-{}", std::process::id()));
+        let path = dir.join("clipboard-history");
+        let mut history = ClipboardHistory::with_path(3, &path);
+        for text in ["a", "b", "c"] {
+            push_text(&mut history, text);
+        }
+        drop(history);
+
+        let loaded = ClipboardHistory::with_path(3, &path);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(texts(&loaded), ["c", "b", "a"]);
+    }
+}
@@ -0,0 +1,663 @@
+//! Lets apps act as the input method of a seat, e.g. a candidate picker or a snippet inserter.
+//!
+//! Only one input method can exist per seat, so it is opt-in through
+//! [`crate::application::Msg::InputMethod`].
+
+use sctk::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_v3::{
+    ChangeCause, ContentHint, ContentPurpose,
+};
+use sctk::reexports::protocols_misc::zwp_input_method_v2::client::{
+    zwp_input_method_manager_v2::ZwpInputMethodManagerV2,
+    zwp_input_method_v2::{self, ZwpInputMethodV2},
+    zwp_input_popup_surface_v2::{self, ZwpInputPopupSurfaceV2},
+};
+use tracing::{info, warn};
+use wayland_backend::client::ObjectId;
+use wayland_client::{
+    delegate_noop, protocol::wl_surface::WlSurface, Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+
+use super::{SurfaceId, WgpuLayerShellState};
+use crate::application::{SurfaceEvent, WPEvent};
+
+delegate_noop!(WgpuLayerShellState: ignore ZwpInputMethodManagerV2);
+
+/// What changed about the text input served by the input method, sent as
+/// [`WPEvent::InputMethod`] once the compositor applied it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputMethodEvent {
+    /// A text input focused on the seat wants the input method. Its surrounding text and
+    /// content type follow.
+    Activated,
+    /// No focused text input needs the input method anymore.
+    Deactivated,
+    /// Text around the cursor. `cursor` and `anchor` are byte offsets into `text`, the
+    /// selection lies between them.
+    SurroundingText {
+        text: String,
+        cursor: u32,
+        anchor: u32,
+        cause: ChangeCause,
+    },
+    ContentType {
+        hint: ContentHint,
+        purpose: ContentPurpose,
+    },
+    /// Another input method already serves the seat, or the seat is gone. The input method
+    /// is destroyed along with its popups.
+    Unavailable,
+}
+
+/// Text changes applied to the text input in a single commit, see
+/// [`crate::application::Msg::InputMethodCommit`].
+///
+/// The text input deletes the surrounding text first, then inserts `commit_string` and
+/// finally shows `preedit` at the cursor.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputMethodCommit {
+    /// Name of the seat whose input method commits, `None` for the first active one.
+    pub seat: Option<String>,
+    /// Bytes to delete before and after the cursor, counted from the ends of the preedit.
+    pub delete_surrounding: Option<(u32, u32)>,
+    pub commit_string: Option<String>,
+    /// Composing text and the byte range of the cursor in it, `None` to hide the cursor.
+    /// An empty preedit clears the previous one.
+    pub preedit: Option<(String, Option<(u32, u32)>)>,
+}
+
+impl InputMethodCommit {
+    /// Inserts `text` at the cursor, replacing the preedit.
+    pub fn commit_string(text: impl Into<String>) -> Self {
+        Self {
+            commit_string: Some(text.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Shows `text` as composing text with the cursor at its end.
+    pub fn preedit(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let end = text.len() as u32;
+        Self {
+            preedit: Some((text, Some((end, end)))),
+            ..Default::default()
+        }
+    }
+
+    /// Deletes `before` bytes before and `after` bytes after the cursor.
+    pub fn delete_surrounding_text(before: u32, after: u32) -> Self {
+        Self {
+            delete_surrounding: Some((before, after)),
+            ..Default::default()
+        }
+    }
+}
+
+/// State of the text input as last told by the compositor.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextInputInfo {
+    pub active: bool,
+    /// Text around the cursor, with the cursor and anchor byte offsets.
+    pub surrounding_text: Option<(String, u32, u32)>,
+    pub change_cause: Option<ChangeCause>,
+    pub content_type: Option<(ContentHint, ContentPurpose)>,
+}
+
+/// The input method object of a seat.
+pub struct InputMethod {
+    input_method: ZwpInputMethodV2,
+    /// Changed by the events, applied on `done`.
+    pending: TextInputInfo,
+    current: TextInputInfo,
+    /// Number of `done` events received, sent back with every commit.
+    serial: u32,
+}
+
+impl InputMethod {
+    pub(crate) fn new(input_method: ZwpInputMethodV2) -> Self {
+        Self {
+            input_method,
+            pending: TextInputInfo::default(),
+            current: TextInputInfo::default(),
+            serial: 0,
+        }
+    }
+
+    /// The text input the input method serves, as of the last `done` event.
+    pub fn text_input(&self) -> &TextInputInfo {
+        &self.current
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current.active
+    }
+
+    fn commit(&mut self, commit: InputMethodCommit) {
+        for request in commit_requests(commit, self.serial) {
+            // Fails only once the input method is dead.
+            let _ = self.input_method.send_request(request);
+        }
+    }
+
+    /// Applies an event of the compositor, returning what changed once it is done.
+    fn handle_event(&mut self, event: zwp_input_method_v2::Event) -> Vec<InputMethodEvent> {
+        let pending = &mut self.pending;
+        match event {
+            zwp_input_method_v2::Event::Activate => {
+                // Resets everything the previous text input left behind.
+                *pending = TextInputInfo {
+                    active: true,
+                    ..Default::default()
+                };
+            }
+            zwp_input_method_v2::Event::Deactivate => pending.active = false,
+            zwp_input_method_v2::Event::SurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => pending.surrounding_text = Some((text, cursor, anchor)),
+            zwp_input_method_v2::Event::TextChangeCause { cause } => {
+                pending.change_cause = cause.into_result().ok();
+            }
+            zwp_input_method_v2::Event::ContentType { hint, purpose } => {
+                let hint = match hint {
+                    WEnum::Value(hint) => hint,
+                    WEnum::Unknown(bits) => ContentHint::from_bits_truncate(bits),
+                };
+                let purpose = purpose.into_result().unwrap_or(ContentPurpose::Normal);
+                pending.content_type = Some((hint, purpose));
+            }
+            zwp_input_method_v2::Event::Done => return self.done(),
+            zwp_input_method_v2::Event::Unavailable => {
+                // No text input is served anymore, nor will be.
+                self.pending = TextInputInfo::default();
+                self.current = TextInputInfo::default();
+                return vec![InputMethodEvent::Unavailable];
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    /// Applies the pending state, returning the events describing what changed.
+    fn done(&mut self) -> Vec<InputMethodEvent> {
+        self.serial = self.serial.wrapping_add(1);
+        let previous = std::mem::replace(&mut self.current, self.pending.clone());
+        let current = &self.current;
+        let mut events = Vec::new();
+        if current.active != previous.active {
+            events.push(if current.active {
+                InputMethodEvent::Activated
+            } else {
+                InputMethodEvent::Deactivated
+            });
+        }
+        if current.surrounding_text != previous.surrounding_text {
+            if let Some((text, cursor, anchor)) = current.surrounding_text.clone() {
+                events.push(InputMethodEvent::SurroundingText {
+                    text,
+                    cursor,
+                    anchor,
+                    cause: current.change_cause.unwrap_or(ChangeCause::Other),
+                });
+            }
+        }
+        if current.content_type != previous.content_type {
+            if let Some((hint, purpose)) = current.content_type {
+                events.push(InputMethodEvent::ContentType { hint, purpose });
+            }
+        }
+        events
+    }
+
+    pub(crate) fn destroy(self) {
+        self.input_method.destroy();
+    }
+}
+
+/// Requests applying `commit` in a single commit of the input method.
+fn commit_requests(
+    commit: InputMethodCommit,
+    serial: u32,
+) -> Vec<zwp_input_method_v2::Request<'static>> {
+    use zwp_input_method_v2::Request;
+
+    let mut requests = Vec::new();
+    if let Some((before_length, after_length)) = commit.delete_surrounding {
+        requests.push(Request::DeleteSurroundingText {
+            before_length,
+            after_length,
+        });
+    }
+    if let Some(text) = commit.commit_string {
+        requests.push(Request::CommitString { text });
+    }
+    if let Some((text, cursor)) = commit.preedit {
+        let (cursor_begin, cursor_end) = cursor.map_or((-1, -1), |(b, e)| (b as i32, e as i32));
+        requests.push(Request::SetPreeditString {
+            text,
+            cursor_begin,
+            cursor_end,
+        });
+    }
+    requests.push(Request::Commit { serial });
+    requests
+}
+
+/// The role of a surface shown by the compositor next to the text cursor while the input
+/// method is active.
+pub struct InputPopup {
+    popup: ZwpInputPopupSurfaceV2,
+    wl_surface: WlSurface,
+    /// The input method the popup was created from.
+    input_method: ObjectId,
+    /// Name of the seat the popup was asked for, kept to re-create it.
+    pub(crate) seat: Option<String>,
+    /// Where the text being entered is, relative to the popup.
+    pub(crate) text_input_rectangle: Option<(i32, i32, i32, i32)>,
+}
+
+impl InputPopup {
+    pub fn wl_surface(&self) -> &WlSurface {
+        &self.wl_surface
+    }
+}
+
+impl Drop for InputPopup {
+    fn drop(&mut self) {
+        // The role must go before the wl_surface.
+        self.popup.destroy();
+        self.wl_surface.destroy();
+    }
+}
+
+impl WgpuLayerShellState {
+    /// Acts as the input method of every seat, or stops doing so.
+    ///
+    /// A seat takes only one input method, if another one runs already
+    /// [`InputMethodEvent::Unavailable`] is sent. Stopping destroys the input popups.
+    pub fn set_input_method(&mut self, enabled: bool) {
+        if self.input_method_enabled == enabled {
+            return;
+        }
+        self.input_method_enabled = enabled;
+        if enabled {
+            if self.input_method_manager.is_none() {
+                warn!("zwp_input_method_manager_v2 not available");
+            }
+            let seats: Vec<_> = self.seat_map.keys().cloned().collect();
+            for seat in seats {
+                self.create_input_method(&seat);
+            }
+            return;
+        }
+
+        let popups: Vec<_> = self
+            .surfaces
+            .values()
+            .filter(|s| s.role.input_popup().is_some())
+            .map(|s| s.id())
+            .collect();
+        for id in popups {
+            self.remove_surface(id);
+        }
+        for per_seat in self.seat_map.values_mut() {
+            if let Some(input_method) = per_seat.input_method.take() {
+                input_method.destroy();
+            }
+        }
+    }
+
+    /// Creates the input method of a seat if acting as one and it has none yet.
+    pub(crate) fn create_input_method(&mut self, seat: &ObjectId) {
+        let Some(manager) = self
+            .input_method_manager
+            .as_ref()
+            .filter(|_| self.input_method_enabled)
+        else {
+            return;
+        };
+        let Some(per_seat) = self.seat_map.get_mut(seat) else {
+            return;
+        };
+        if per_seat.input_method.is_none() {
+            let input_method = manager.get_input_method(&per_seat.seat, &self.queue_handle, ());
+            per_seat.input_method = Some(InputMethod::new(input_method));
+        }
+    }
+
+    /// The input method of the seat with this name, or of the first seat it is active on.
+    pub fn input_method(&self, seat: Option<&str>) -> Option<&InputMethod> {
+        let seat = self.input_method_seat(seat)?;
+        self.seat_map[&seat].input_method.as_ref()
+    }
+
+    fn input_method_seat(&self, name: Option<&str>) -> Option<ObjectId> {
+        let with_input_method = || {
+            self.seat_map
+                .iter()
+                .filter(|(_, s)| s.input_method.is_some())
+        };
+        match name {
+            Some(name) => with_input_method()
+                .find(|(id, _)| self.seat_name(id).as_deref() == Some(name))
+                .map(|(id, _)| id.clone()),
+            None => with_input_method()
+                .find(|(_, s)| s.input_method.as_ref().is_some_and(|im| im.is_active()))
+                .or_else(|| with_input_method().next())
+                .map(|(id, _)| id.clone()),
+        }
+    }
+
+    /// Applies text changes to the text input the input method serves.
+    pub fn input_method_commit(&mut self, commit: InputMethodCommit) {
+        let Some(seat) = self.input_method_seat(commit.seat.as_deref()) else {
+            warn!("no input method to commit {:?} with", commit);
+            return;
+        };
+        if let Some(input_method) = self
+            .seat_map
+            .get_mut(&seat)
+            .and_then(|s| s.input_method.as_mut())
+        {
+            input_method.commit(commit);
+        }
+    }
+
+    /// Gives `wl_surface` the input popup role of the seat's input method, or of the first one
+    /// available.
+    pub(crate) fn create_input_popup(
+        &self,
+        id: SurfaceId,
+        seat: Option<&str>,
+    ) -> Option<InputPopup> {
+        let input_method = self.input_method(seat)?;
+        let wl_surface = self.compositor.create_surface(&self.queue_handle);
+        let popup =
+            input_method
+                .input_method
+                .get_input_popup_surface(&wl_surface, &self.queue_handle, id);
+        Some(InputPopup {
+            popup,
+            wl_surface,
+            input_method: input_method.input_method.id(),
+            seat: seat.map(str::to_owned),
+            text_input_rectangle: None,
+        })
+    }
+
+    /// Destroys the input method of a seat, after the popups created from it.
+    fn drop_input_method(&mut self, seat: &ObjectId) {
+        let Some(input_method) = self
+            .seat_map
+            .get_mut(seat)
+            .and_then(|s| s.input_method.take())
+        else {
+            return;
+        };
+        let id = input_method.input_method.id();
+        let popups: Vec<_> = self
+            .surfaces
+            .values()
+            .filter(|s| s.role.input_popup().is_some_and(|p| p.input_method == id))
+            .map(|s| s.id())
+            .collect();
+        for popup in popups {
+            self.remove_surface(popup);
+        }
+        input_method.destroy();
+    }
+
+    fn seat_of_input_method(&self, input_method: &ZwpInputMethodV2) -> Option<ObjectId> {
+        self.seat_map
+            .iter()
+            .find(|(_, s)| {
+                s.input_method
+                    .as_ref()
+                    .is_some_and(|im| &im.input_method == input_method)
+            })
+            .map(|(id, _)| id.clone())
+    }
+}
+
+impl Dispatch<ZwpInputMethodV2, ()> for WgpuLayerShellState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwpInputMethodV2,
+        event: <ZwpInputMethodV2 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let Some(seat) = state.seat_of_input_method(proxy) else {
+            return;
+        };
+        let name = state.seat_name(&seat);
+        let Some(input_method) = state
+            .seat_map
+            .get_mut(&seat)
+            .and_then(|s| s.input_method.as_mut())
+        else {
+            return;
+        };
+        let unavailable = matches!(event, zwp_input_method_v2::Event::Unavailable);
+        let events = input_method.handle_event(event);
+        if unavailable {
+            info!("input method of seat {:?} unavailable", name);
+            state.drop_input_method(&seat);
+        }
+        for event in events {
+            state.emit(WPEvent::InputMethod {
+                seat: name.clone(),
+                event,
+            });
+        }
+    }
+}
+
+impl Dispatch<ZwpInputPopupSurfaceV2, SurfaceId> for WgpuLayerShellState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwpInputPopupSurfaceV2,
+        event: <ZwpInputPopupSurfaceV2 as Proxy>::Event,
+        id: &SurfaceId,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let zwp_input_popup_surface_v2::Event::TextInputRectangle {
+            x,
+            y,
+            width,
+            height,
+        } = event
+        else {
+            return;
+        };
+        let Some(surface) = state.surfaces.get_mut(id) else {
+            return;
+        };
+        if let Some(popup) = surface.role.input_popup_mut() {
+            popup.text_input_rectangle = Some((x, y, width, height));
+        }
+        surface.emit(SurfaceEvent::TextInputRectangle {
+            x,
+            y,
+            width,
+            height,
+        });
+        surface.egui_state.context().request_repaint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use wayland_backend::client::Backend;
+    use zwp_input_method_v2::{Event, Request};
+
+    use super::*;
+
+    /// An input method without a compositor, its requests go nowhere.
+    fn input_method() -> InputMethod {
+        let (stream, _) = UnixStream::pair().unwrap();
+        let backend = Backend::connect(stream).unwrap();
+        InputMethod::new(ZwpInputMethodV2::inert(backend.downgrade()))
+    }
+
+    fn activate(input_method: &mut InputMethod) -> Vec<InputMethodEvent> {
+        input_method.handle_event(Event::Activate);
+        input_method.handle_event(Event::SurroundingText {
+            text: "héllo".into(),
+            cursor: 6,
+            anchor: 1,
+        });
+        input_method.handle_event(Event::TextChangeCause {
+            cause: WEnum::Value(ChangeCause::InputMethod),
+        });
+        input_method.handle_event(Event::ContentType {
+            hint: WEnum::Unknown(ContentHint::Multiline.bits() | 0x8000_0000),
+            purpose: WEnum::Unknown(99),
+        });
+        input_method.handle_event(Event::Done)
+    }
+
+    #[test]
+    fn done_applies_pending_state() {
+        let mut input_method = input_method();
+        let events = activate(&mut input_method);
+        assert_eq!(
+            events,
+            [
+                InputMethodEvent::Activated,
+                InputMethodEvent::SurroundingText {
+                    text: "héllo".into(),
+                    cursor: 6,
+                    anchor: 1,
+                    cause: ChangeCause::InputMethod,
+                },
+                InputMethodEvent::ContentType {
+                    hint: ContentHint::Multiline,
+                    purpose: ContentPurpose::Normal,
+                },
+            ]
+        );
+        assert!(input_method.is_active());
+        assert_eq!(input_method.serial, 1);
+
+        // Nothing changed.
+        assert_eq!(input_method.handle_event(Event::Done), []);
+        assert_eq!(input_method.serial, 2);
+
+        input_method.handle_event(Event::Deactivate);
+        assert!(input_method.is_active(), "applied on done");
+        assert_eq!(
+            input_method.handle_event(Event::Done),
+            [InputMethodEvent::Deactivated]
+        );
+        assert!(!input_method.is_active());
+    }
+
+    #[test]
+    fn activate_resets_text_input() {
+        let mut input_method = input_method();
+        activate(&mut input_method);
+        input_method.handle_event(Event::Deactivate);
+        input_method.handle_event(Event::Done);
+        input_method.handle_event(Event::Activate);
+        assert_eq!(
+            input_method.handle_event(Event::Done),
+            [InputMethodEvent::Activated]
+        );
+        assert_eq!(
+            *input_method.text_input(),
+            TextInputInfo {
+                active: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn unavailable_clears_state() {
+        let mut input_method = input_method();
+        activate(&mut input_method);
+        input_method.handle_event(Event::Deactivate);
+        assert_eq!(
+            input_method.handle_event(Event::Unavailable),
+            [InputMethodEvent::Unavailable]
+        );
+        assert!(!input_method.is_active());
+        assert_eq!(*input_method.text_input(), TextInputInfo::default());
+        assert_eq!(input_method.handle_event(Event::Done), []);
+    }
+
+    #[test]
+    fn commits_in_protocol_order() {
+        let cases = [
+            (
+                InputMethodCommit::commit_string("é"),
+                vec![
+                    Request::CommitString { text: "é".into() },
+                    Request::Commit { serial: 3 },
+                ],
+            ),
+            (
+                InputMethodCommit::preedit("ka"),
+                vec![
+                    Request::SetPreeditString {
+                        text: "ka".into(),
+                        cursor_begin: 2,
+                        cursor_end: 2,
+                    },
+                    Request::Commit { serial: 3 },
+                ],
+            ),
+            (
+                InputMethodCommit::delete_surrounding_text(2, 1),
+                vec![
+                    Request::DeleteSurroundingText {
+                        before_length: 2,
+                        after_length: 1,
+                    },
+                    Request::Commit { serial: 3 },
+                ],
+            ),
+            (
+                InputMethodCommit {
+                    preedit: Some((String::new(), None)),
+                    commit_string: Some("か".into()),
+                    delete_surrounding: Some((2, 0)),
+                    ..Default::default()
+                },
+                vec![
+                    Request::DeleteSurroundingText {
+                        before_length: 2,
+                        after_length: 0,
+                    },
+                    Request::CommitString { text: "か".into() },
+                    Request::SetPreeditString {
+                        text: String::new(),
+                        cursor_begin: -1,
+                        cursor_end: -1,
+                    },
+                    Request::Commit { serial: 3 },
+                ],
+            ),
+            (
+                InputMethodCommit::default(),
+                vec![Request::Commit { serial: 3 }],
+            ),
+        ];
+        for (commit, requests) in cases {
+            let description = format!("{:?}", commit);
+            // Requests are not PartialEq.
+            assert_eq!(
+                format!("{:?}", commit_requests(commit, 3)),
+                format!("{:?}", requests),
+                "{}",
+                description
+            );
+        }
+    }
+}
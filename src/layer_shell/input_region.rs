@@ -0,0 +1,112 @@
+use egui::{containers::AreaState, Rect};
+use sctk::{compositor::CompositorState, shell::WaylandSurface};
+use wayland_client::QueueHandle;
+
+use super::{PopupSurface, SurfaceId, WgpuLayerShellState};
+
+/// `(x, y, width, height)` in surface-local coordinates, as taken by `wl_region::add`.
+pub(crate) type RegionRect = (i32, i32, i32, i32);
+
+/// `rect` in points scaled by `zoom`, grown to whole pixels. `None` if it is empty.
+fn region_rect(rect: Rect, zoom: f32) -> Option<RegionRect> {
+    let rect = Rect::from_min_max(rect.min * zoom, rect.max * zoom);
+    let (min, max) = (rect.min.floor(), rect.max.ceil());
+    let (width, height) = ((max.x - min.x) as i32, (max.y - min.y) as i32);
+    (width > 0 && height > 0).then_some((min.x as i32, min.y as i32, width, height))
+}
+
+/// Rects of the interactable areas and windows laid out in the last pass, in logical pixels.
+///
+/// Panels have no area of their own and are not part of it, their parts of the surface pass
+/// clicks through unless covered by an area.
+fn interactive_rects(ctx: &egui::Context) -> Vec<RegionRect> {
+    let layers = ctx.memory(|m| m.areas().visible_layer_ids());
+    let zoom = ctx.zoom_factor();
+    let mut rects: Vec<RegionRect> = layers
+        .into_iter()
+        .filter_map(|layer| {
+            let state = AreaState::load(ctx, layer.id)?;
+            if !state.interactable {
+                return None;
+            }
+            let rect = match ctx.layer_transform_to_global(layer) {
+                Some(transform) => transform * state.rect(),
+                None => state.rect(),
+            };
+            region_rect(rect, zoom)
+        })
+        .collect();
+    // The visible layers come from a hash set.
+    rects.sort_unstable();
+    rects
+}
+
+impl PopupSurface {
+    pub fn auto_passthrough(&self) -> bool {
+        self.auto_passthrough
+    }
+
+    /// Limits input to the interactive areas, if they changed since the last frame.
+    pub(crate) fn update_input_region(
+        &mut self,
+        compositor: &CompositorState,
+        qh: &QueueHandle<WgpuLayerShellState>,
+    ) {
+        if !self.auto_passthrough || self.passthrough {
+            return;
+        }
+        let rects = interactive_rects(self.egui_state.context());
+        if self.input_region.as_ref() == Some(&rects) {
+            return;
+        }
+        let region = compositor.wl_compositor().create_region(qh, ());
+        for &(x, y, width, height) in &rects {
+            region.add(x, y, width, height);
+        }
+        self.role.set_input_region(Some(&region));
+        region.destroy();
+        self.input_region = Some(rects);
+    }
+}
+
+impl WgpuLayerShellState {
+    /// Lets clicks outside the interactive egui areas fall through to the window below.
+    ///
+    /// [`WgpuLayerShellState::set_passthrough`] takes precedence while enabled.
+    pub fn set_auto_passthrough(&mut self, id: SurfaceId, auto: bool) {
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return;
+        };
+        surface.auto_passthrough = auto;
+        surface.input_region = None;
+        if !auto && !surface.passthrough {
+            surface.role.set_input_region(None);
+            surface.role.commit();
+        }
+        surface.egui_state.context().request_repaint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::pos2;
+
+    use super::*;
+
+    #[test]
+    fn grows_to_whole_pixels() {
+        let cases = [
+            // min, max, zoom, region
+            ((0., 0.), (10., 20.), 1., Some((0, 0, 10, 20))),
+            ((0.5, 1.2), (10.2, 20.), 1., Some((0, 1, 11, 19))),
+            ((10., 10.), (20., 30.), 1.5, Some((15, 15, 15, 30))),
+            ((10., 10.), (11., 11.), 0.5, Some((5, 5, 1, 1))),
+            ((5., 5.), (5., 20.), 1., None),
+            ((5., 5.), (3., 3.), 1., None),
+        ];
+        for (min, max, zoom, region) in cases {
+            let rect = Rect::from_min_max(pos2(min.0, min.1), pos2(max.0, max.1));
+            assert_eq!(region_rect(rect, zoom), region, "{:?} * {}", rect, zoom);
+        }
+    }
+}
@@ -5,6 +5,8 @@ use sctk::{
 };
 use wayland_client::{protocol::wl_surface, Connection, QueueHandle};
 
+use crate::application::SurfaceEvent;
+
 use super::WgpuLayerShellState;
 
 delegate_keyboard!(WgpuLayerShellState);
@@ -14,13 +16,24 @@ impl KeyboardHandler for WgpuLayerShellState {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
-        _surface: &wl_surface::WlSurface,
-        _serial: u32,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        surface: &wl_surface::WlSurface,
+        serial: u32,
         _raw: &[u32],
         _keysyms: &[sctk::seat::keyboard::Keysym],
     ) {
-        let input = self.egui_state.input();
+        let Some(seat) = self.seat_of_keyboard(keyboard) else {
+            return;
+        };
+        self.set_input_serial(&seat, serial);
+        self.set_seat_focus(&seat, self.surface_id_of(surface));
+        let name = self.seat_name(&seat);
+        if let Some(surface) = self.surface_of_mut(surface) {
+            surface.emit(SurfaceEvent::FocusGained { seat: name });
+        }
+        let Some(input) = self.focused_input(&seat) else {
+            return;
+        };
         input.focused = true;
         // todo: this should probably be in surface enter?
         input.events.push(egui::Event::WindowFocused(true));
@@ -30,11 +43,28 @@ impl KeyboardHandler for WgpuLayerShellState {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
-        _surface: &wl_surface::WlSurface,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        surface: &wl_surface::WlSurface,
         _serial: u32,
     ) {
-        let input = self.egui_state.input();
+        let Some(seat) = self.seat_of_keyboard(keyboard) else {
+            return;
+        };
+        self.set_seat_focus(&seat, None);
+        let name = self.seat_name(&seat);
+        let Some(id) = self.surface_id_of(surface) else {
+            return;
+        };
+        // Another seat may still type into the surface.
+        let still_focused = !self.seats_focused_on(id).is_empty();
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return;
+        };
+        surface.emit(SurfaceEvent::FocusLost { seat: name });
+        if still_focused {
+            return;
+        }
+        let input = surface.egui_state.input();
         input.focused = false;
         // todo: this should probably be in surface enter?
         input.events.push(egui::Event::WindowFocused(false));
@@ -44,22 +74,47 @@ impl KeyboardHandler for WgpuLayerShellState {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
-        _serial: u32,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        serial: u32,
         event: sctk::seat::keyboard::KeyEvent,
     ) {
-        handle_key_press(event, true, self.egui_state.input());
+        let Some(seat) = self.seat_of_keyboard(keyboard) else {
+            return;
+        };
+        self.set_input_serial(&seat, serial);
+        let Some(focus) = self.seat_map[&seat].keyboard_focus else {
+            return;
+        };
+        // The last seat to type decides where text input happens.
+        self.keyboard_focus = Some(focus);
+        let modifiers = self.seat_map[&seat].modifiers;
+        let Some(input) = self.focused_input(&seat) else {
+            return;
+        };
+        input.modifiers = modifiers;
+        if is_paste_shortcut(event.keysym, modifiers) {
+            self.paste(focus);
+            return;
+        }
+        handle_key_press(event, true, input);
     }
 
     fn release_key(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _serial: u32,
         event: sctk::seat::keyboard::KeyEvent,
     ) {
-        handle_key_press(event, false, self.egui_state.input());
+        let Some(seat) = self.seat_of_keyboard(keyboard) else {
+            return;
+        };
+        let modifiers = self.seat_map[&seat].modifiers;
+        if let Some(input) = self.focused_input(&seat) {
+            input.modifiers = modifiers;
+            handle_key_press(event, false, input);
+        }
     }
 
     fn update_modifiers(
@@ -72,13 +127,23 @@ impl KeyboardHandler for WgpuLayerShellState {
         raw_modifiers: sctk::seat::keyboard::RawModifiers,
         layout: u32,
     ) {
-        self.egui_state.input().modifiers = Modifiers {
+        let modifiers = Modifiers {
             alt: modifiers.alt,
             ctrl: modifiers.ctrl,
             shift: modifiers.shift,
             mac_cmd: false, // this is linux only
             command: modifiers.ctrl,
         };
+        let Some(seat) = self.seat_of_keyboard(keyboard) else {
+            return;
+        };
+        // Pointer events of the seat read them from here.
+        if let Some(per_seat) = self.seat_map.get_mut(&seat) {
+            per_seat.modifiers = modifiers;
+        }
+        if let Some(input) = self.focused_input(&seat) {
+            input.modifiers = modifiers;
+        }
     }
 
     fn repeat_key(
@@ -100,10 +165,6 @@ fn handle_clipboard_shortcuts(
     let event = match (key, modifiers.ctrl) {
         (egui::Key::C, true) => Some(egui::Event::Copy),
         (egui::Key::X, true) => Some(egui::Event::Cut),
-        (egui::Key::V, true) => {
-            // todo: implement paste
-            None
-        }
         _ => None,
     };
 
@@ -115,6 +176,16 @@ fn handle_clipboard_shortcuts(
     false
 }
 
+/// Pasting needs the clipboard read first, see [`WgpuLayerShellState::paste`].
+fn is_paste_shortcut(keysym: Keysym, modifiers: Modifiers) -> bool {
+    match keysym_to_egui_key(keysym) {
+        Some(egui::Key::V) => modifiers.ctrl,
+        Some(egui::Key::Insert) => modifiers.shift,
+        Some(egui::Key::Paste) => true,
+        _ => false,
+    }
+}
+
 pub fn handle_key_press(event: KeyEvent, pressed: bool, egui_input: &mut RawInput) {
     if let Some(key) = keysym_to_egui_key(event.keysym) {
         if pressed && handle_clipboard_shortcuts(key, egui_input.modifiers, egui_input) {
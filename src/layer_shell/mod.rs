@@ -1,15 +1,28 @@
+mod auto_size;
+mod clipboard;
+mod fractional_scale;
+mod history;
+mod input_method;
+mod input_region;
 mod keyboard_handler;
+mod output_handler;
+mod placement;
 mod pointer_handler;
+mod reconnect;
+mod reconfigure;
+mod seat;
+pub(crate) mod selection;
+mod transition;
+mod virtual_keyboard;
 
 use std::{
     collections::BTreeMap,
-    io::PipeReader,
-    sync::{Arc, RwLock},
+    sync::Arc,
     time::{Duration, Instant},
     u32,
 };
 
-use dpi::{LogicalPosition, LogicalSize, PhysicalPosition, PhysicalSize, Position, Size};
+use dpi::{PhysicalPosition, PhysicalSize, Position, Size};
 use egui::{
     ahash::{AHashMap, HashMap},
     PlatformOutput, ViewportCommand,
@@ -18,18 +31,23 @@ use egui_wgpu::ScreenDescriptor;
 use keyboard_handler::handle_key_press;
 pub use sctk::{
     compositor::{CompositorHandler, CompositorState},
-    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_seat,
+    delegate_compositor, delegate_layer, delegate_registry, delegate_seat,
     output::{OutputHandler, OutputState},
     reexports::{
-        calloop::LoopHandle,
+        calloop::{self, channel::Channel, LoopHandle, RegistrationToken},
         calloop_wayland_source::WaylandSource,
         protocols::{
             ext::background_effect::v1::client::{
                 ext_background_effect_manager_v1::{self, ExtBackgroundEffectManagerV1},
                 ext_background_effect_surface_v1,
             },
-            wp::text_input::zv3::client::{
-                zwp_text_input_manager_v3::ZwpTextInputManagerV3, zwp_text_input_v3::ZwpTextInputV3,
+            wp::{
+                fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+                text_input::zv3::client::{
+                    zwp_text_input_manager_v3::ZwpTextInputManagerV3,
+                    zwp_text_input_v3::ZwpTextInputV3,
+                },
+                viewporter::client::wp_viewporter::WpViewporter,
             },
         },
         protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1,
@@ -52,10 +70,16 @@ use sctk::{
     reexports::{
         protocols::{
             ext::data_control::v1::client::ext_data_control_manager_v1,
-            wp::primary_selection::zv1::client::zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1,
-        }, protocols_misc::zwp_virtual_keyboard_v1::client::{zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1, zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1}, protocols_wlr::data_control::v1::client::{
-            zwlr_data_control_device_v1, zwlr_data_control_manager_v1,
-        }
+            wp::primary_selection::zv1::client::zwp_primary_selection_device_manager_v1::ZwpPrimarySelectionDeviceManagerV1,
+        },
+        protocols_misc::{
+            zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2,
+            zwp_virtual_keyboard_v1::client::{
+                zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1,
+                zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+            },
+        },
+        protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1,
     },
 };
 use tokio::sync::mpsc;
@@ -65,7 +89,7 @@ use wayland_client::{
     delegate_dispatch, delegate_noop,
     globals::registry_queue_init,
     protocol::{
-        wl_keyboard::WlKeyboard, wl_output, wl_pointer::WlPointer, wl_region::WlRegion, wl_seat,
+        wl_data_device_manager::WlDataDeviceManager, wl_output, wl_region::WlRegion, wl_seat,
         wl_surface,
     },
     Connection, Proxy, QueueHandle,
@@ -74,18 +98,30 @@ use wayland_protocols_plasma::blur::client::org_kde_kwin_blur::OrgKdeKwinBlur;
 use wayland_protocols_plasma::blur::client::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager;
 
 use crate::{
-    application::WPEvent,
+    application::{handle_msg, EvRx, ExitReason, Msg, MsgQueue, SurfaceEvent, WPEvent},
     egui_state::{self},
-    layer_shell::cliphandler::WlListenType,
     text_input::{
-        ImeCapabilities, ImeEnableRequest, ImeHint, ImePurpose, ImeRequest, ImeRequestData,
-        ImeSurroundingText, TextInputClientState, TextInputData, TextInputState, ZwpTextInputV3Ext,
+        ime_transition, ImeHint, ImePurpose, ImeRequest, ImeRequestData, ImeSurroundingText,
+        TextInputData, TextInputState,
     },
-    wgpu_state::WgpuState,
-    App,
+    errors::InitError,
+    wgpu_state::{WgpuState, WgpuStateError, WgpuSurface},
+    AppCreator,
 };
 
-#[derive(Default)]
+/// How [`Msg::Hide`] and [`Msg::Toggle`] hide a surface.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HideStrategy {
+    /// Move the surface to the background layer and back to [`LayerShellOptions::layer`].
+    /// Cheap, but the surface stays mapped: it covers the wallpaper and still takes input.
+    #[default]
+    LayerSwap,
+    /// Unmap the surface by attaching a null buffer. It is mapped again on show and redrawn
+    /// after the next configure, keeping the egui context and the [`crate::App`].
+    Unmap,
+}
+
+#[derive(Default, Clone, Debug)]
 pub struct LayerShellOptions {
     pub layer: Option<Layer>,
     pub namespace: String,
@@ -94,38 +130,108 @@ pub struct LayerShellOptions {
     pub anchor: Option<Anchor>,
     pub keyboard_interactivity: Option<KeyboardInteractivity>,
     pub margin: (i32, i32, i32, i32),
+    /// Space in logical pixels other surfaces keep clear of at the anchored edge, `-1` to
+    /// ignore the zones of others. Only honoured when anchored to a single edge, or to an
+    /// edge and both edges perpendicular to it. `None` leaves the compositor default of 0.
+    pub exclusive_zone: Option<i32>,
+    /// Output to place the surface on. `None` lets the compositor choose.
+    pub output: Option<OutputSelector>,
+    /// Size the surface after its content, `width` and `height` are the initial size.
+    pub auto_size: Option<AutoSize>,
+    pub hide_strategy: HideStrategy,
+    /// Played when the surface is shown after being hidden.
+    pub open_transition: Option<Transition>,
+    /// Played before the surface is hidden.
+    pub close_transition: Option<Transition>,
+}
+
+impl LayerShellOptions {
+    /// A bar along `edge`, stretched across the output, that other windows keep clear of.
+    pub fn panel(edge: Anchor, thickness: u32) -> Self {
+        let (anchor, width, height) = if edge == Anchor::LEFT || edge == Anchor::RIGHT {
+            (edge | Anchor::TOP | Anchor::BOTTOM, thickness, 0)
+        } else {
+            (edge | Anchor::LEFT | Anchor::RIGHT, 0, thickness)
+        };
+        if exclusive_edge(anchor).is_none() {
+            warn!("panel edge {:?} is not a single edge", edge);
+        }
+        Self {
+            layer: Some(Layer::Top),
+            width,
+            height,
+            anchor: Some(anchor),
+            keyboard_interactivity: Some(KeyboardInteractivity::None),
+            exclusive_zone: Some(thickness as i32),
+            ..Default::default()
+        }
+    }
+
+    /// Applies the options to a layer surface and commits it.
+    pub fn apply(&self, layer_surface: &LayerSurface) {
+        if let Some(anchor) = self.anchor {
+            layer_surface.set_anchor(anchor);
+        }
+        if let Some(keyboard_interactivity) = self.keyboard_interactivity {
+            layer_surface.set_keyboard_interactivity(keyboard_interactivity);
+        }
+        layer_surface.set_size(self.width, self.height);
+        layer_surface.set_opaque_region(None);
+        layer_surface.set_margin(self.margin.0, self.margin.1, self.margin.2, self.margin.3);
+        if let Some(zone) = self.exclusive_zone {
+            if zone > 0 && exclusive_edge(self.anchor.unwrap_or(Anchor::empty())).is_none() {
+                warn!("exclusive zone ignored, {:?} is not anchored to one edge", self.anchor);
+            }
+            layer_surface.set_exclusive_zone(zone);
+        }
+        layer_surface.commit();
+    }
+}
+
+/// The edge an exclusive zone applies to with `anchor`, if any.
+fn exclusive_edge(anchor: Anchor) -> Option<Anchor> {
+    [
+        (Anchor::TOP, Anchor::LEFT | Anchor::RIGHT),
+        (Anchor::BOTTOM, Anchor::LEFT | Anchor::RIGHT),
+        (Anchor::LEFT, Anchor::TOP | Anchor::BOTTOM),
+        (Anchor::RIGHT, Anchor::TOP | Anchor::BOTTOM),
+    ]
+    .into_iter()
+    .find(|&(edge, across)| anchor == edge || anchor == edge | across)
+    .map(|(edge, _)| edge)
 }
 
 pub struct WgpuLayerShellState {
     //event_loop: Arc<EventLoop<'static, Self>>,
     pub loop_handle: LoopHandle<'static, Self>,
+    /// The [`WaylandSource`] of `connection`, removed when reconnecting.
+    wayland_token: RegistrationToken,
+    /// Set while waiting for the compositor to come back, see [`Self::reconnect`].
+    pub(crate) reconnecting: bool,
+    /// Why the compositor could not be reached again.
+    pub(crate) reconnect_error: Option<InitError>,
+    connection: Connection,
     registry_state: RegistryState,
     seat_state: SeatState,
     output_state: OutputState,
     pub(crate) queue_handle: Arc<QueueHandle<Self>>,
 
-    pub(crate) layer: LayerSurface,
-    pub current_layer: Layer,
-    pointer: Option<WlPointer>,
-    keyboard: Option<WlKeyboard>,
-
-    pub(crate) has_frame_callback: bool,
-    is_configured: bool,
-
-    pub(crate) exit: bool,
-
-    pub(crate) wgpu_state: WgpuState,
-    pub egui_state: egui_state::State,
-    pub(crate) draw_request: Arc<RwLock<Option<Instant>>>,
+    layer_shell: LayerShell,
+    /// Created together with the first surface, shared by all others.
+    pub(crate) wgpu_state: Option<WgpuState>,
+    pub(crate) surfaces: BTreeMap<SurfaceId, PopupSurface>,
+    /// The surface whose app is being synced or initialized.
+    active_surface: Option<SurfaceId>,
+    /// Surface focused by the keyboard of a seat, the one to enter last if several are.
+    pub(crate) keyboard_focus: Option<SurfaceId>,
+    /// Seat of the last key press or pointer button, the one to set the clipboard on.
+    pub(crate) input_seat: Option<ObjectId>,
+    /// Set to stop [`crate::application::WgpuLayerShellApp::run_forever`].
+    pub(crate) exit: Option<ExitReason>,
+    pointer_output: Option<wl_output::WlOutput>,
 
-    /// The input method properties provided by the application to the IME.
-    ///
-    /// This state is cached here so that the window can automatically send the state to the IME as
-    /// soon as it becomes available without application involvement.
-    pub text_input_state: Option<TextInputClientState>,
     pub window_text_input_state: Option<TextInputState>,
-    /// The text inputs observed on the window.
-    pub text_inputs: Vec<ZwpTextInputV3>,
+    /// Devices and input state of every seat.
     pub seat_map: AHashMap<ObjectId, PerSeat>,
 
     /// The current IME purpose.
@@ -133,38 +239,53 @@ pub struct WgpuLayerShellState {
 
     /// Whether the IME input is allowed for that window.
     ime_allowed: bool,
-    passthrough: bool,
 
     compositor: CompositorState,
-    pub layer_opts: LayerShellOptions,
+    viewporter: Option<WpViewporter>,
+    fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
 
-    listentype: WlListenType,
-    seat: Option<wl_seat::WlSeat>,
-    seat_name: Option<String>,
     data_manager: Option<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1>,
     ext_data_manager: Option<ext_data_control_manager_v1::ExtDataControlManagerV1>,
-    data_device: Option<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1>,
-    mime_types: Vec<String>,
-    set_priority: Option<Vec<String>>,
-    pipereader: Option<PipeReader>,
-    current_type: Option<String>,
-    copy_data: Option<Vec<u8>>,
-    copy_cancelled: bool,
-
+    primary_selection_manager: Option<ZwpPrimarySelectionDeviceManagerV1>,
+    mime_priority: Vec<String>,
+    selection_delivery: SelectionDelivery,
+    owned_selections: AHashMap<SelectionSource, clipboard::OwnedSelection>,
+    selection_offers: AHashMap<SelectionSource, selection::SelectionOffer>,
+    /// Seat each of the `selection_offers` came from.
+    selection_seats: AHashMap<SelectionSource, ObjectId>,
+    clipboard_history: Option<ClipboardHistory>,
+    data_device_manager: Option<WlDataDeviceManager>,
+
+    /// Queues events until [`Self::deliver_events`] hands them to the `subscribers`.
     pub ev: flume::Sender<WPEvent>,
+    pub(crate) ev_rx: EvRx,
+    subscribers: Vec<flume::Sender<WPEvent>>,
 
-    zwp_data_dev: Option<ZwpPrimarySelectionDeviceV1>,
+    kde_blur: Option<OrgKdeKwinBlurManager>,
     pub has_blur: bool,
     pub virtual_keyboard_manager: Option<ZwpVirtualKeyboardManagerV1>,
-    pub virtual_keyboard: Option<ZwpVirtualKeyboardV1>,
+    input_method_manager: Option<ZwpInputMethodManagerV2>,
+    /// Whether to act as the input method of every seat.
+    input_method_enabled: bool,
 }
 
 pub mod cliphandler;
+mod surface;
 
-#[derive(Default)]
-pub struct PerSeat {
-    pub text_input: Option<Arc<ZwpTextInputV3>>,
-}
+pub use auto_size::{set_content_size, AutoSize};
+pub use clipboard::{text_offers, SelectionOffers};
+pub use history::{ClipboardHistory, HistoryEntry};
+pub use input_method::{
+    InputMethod, InputMethodCommit, InputMethodEvent, InputPopup, TextInputInfo,
+};
+pub use output_handler::OutputSelector;
+pub use placement::place_near;
+pub use reconfigure::LayerShellOptionsPatch;
+pub use seat::PerSeat;
+pub use selection::{SelectionDelivery, SelectionSource};
+pub use surface::{PopupSurface, SurfaceId, SurfaceRole};
+pub use transition::{Easing, Transition, TransitionKind};
+pub use virtual_keyboard::{ChordKey, KeyChord, KeyModifiers, VirtualKeyboard};
 
 delegate_noop!(WgpuLayerShellState: ignore ExtBackgroundEffectManagerV1);
 delegate_noop!(WgpuLayerShellState: ignore OrgKdeKwinBlurManager);
@@ -181,6 +302,40 @@ pub fn pixels_per_point(egui_ctx: &egui::Context, scale: f32) -> f32 {
 }
 
 impl WgpuLayerShellState {
+    /// Whether an [`EvRx`] handed out by [`Self::subscribe`] is alive.
+    pub(crate) fn has_listeners(&self) -> bool {
+        self.subscribers.iter().any(|s| !s.is_disconnected())
+    }
+
+    /// Sends `event` to the apps, dropped when nobody listens so it doesn't pile up.
+    pub(crate) fn emit(&self, event: WPEvent) {
+        if self.has_listeners() {
+            let _ = self.ev.send(event);
+        }
+    }
+
+    /// A new receiver of all events from now on, independent of the others.
+    pub(crate) fn subscribe(&mut self) -> EvRx {
+        let (sx, rx) = flume::unbounded();
+        self.subscribers.push(sx);
+        rx
+    }
+
+    /// Hands the queued events to every subscriber, forgetting those that went away.
+    pub(crate) fn deliver_events(&mut self) {
+        self.subscribers.retain(|s| !s.is_disconnected());
+        for event in self.ev_rx.drain() {
+            if let Some((last, others)) = self.subscribers.split_last() {
+                for subscriber in others {
+                    if let Some(event) = event.duplicate() {
+                        let _ = subscriber.send(event);
+                    }
+                }
+                let _ = last.send(event);
+            }
+        }
+    }
+
     /// Whether the IME is allowed.
     #[inline]
     pub fn ime_allowed(&self) -> bool {
@@ -189,44 +344,24 @@ impl WgpuLayerShellState {
 
     pub fn set_ime_purpose(&mut self, purpose: ImePurpose) {
         self.ime_purpose = purpose;
-
-        for text_input in &self.text_inputs {
-            text_input.set_content_type_by_purpose(purpose);
-            text_input.commit();
+        let data = ImeRequestData::default().with_hint_and_purpose(ImeHint::NONE, purpose);
+        for seat in self.seats_with_ime() {
+            let _ = self.request_ime_update(&seat, ImeRequest::Update(data.clone()));
         }
     }
 
-    pub fn set_ime_cursor_area(&self, position: Position, size: Size) {
-        if self.ime_allowed() {
-            let scale_factor = self.scale_factor();
-            let position = position.to_logical(scale_factor);
-            let size = size.to_logical(scale_factor);
-            self.set_ime_cursor_area_inner(position, size);
+    pub fn set_ime_cursor_area(&mut self, position: Position, size: Size) {
+        let data = ImeRequestData::default().with_cursor_area(position, size);
+        for seat in self.seats_with_ime() {
+            let _ = self.request_ime_update(&seat, ImeRequest::Update(data.clone()));
         }
     }
 
-    /// Set the IME position.
-    pub fn set_ime_cursor_area_inner(
-        &self,
-        position: LogicalPosition<u32>,
-        size: LogicalSize<u32>,
-    ) {
-        // FIXME: This won't fly unless user will have a way to request IME window per seat, since
-        // the ime windows will be overlapping, but winit doesn't expose API to specify for
-        // which seat we're setting IME position.
-        let (x, y) = (position.x as i32, position.y as i32);
-        let (width, height) = (size.width as i32, size.height as i32);
-        for text_input in self.text_inputs.iter() {
-            text_input.set_cursor_rectangle(x, y, width, height);
-            text_input.commit();
-        }
-    }
-
-    pub fn handle_platform(&mut self, platform_output: egui::PlatformOutput) {
+    pub fn handle_platform(&mut self, id: SurfaceId, platform_output: egui::PlatformOutput) {
         let egui::PlatformOutput {
             commands,
             cursor_icon,
-            events: _,                    // handled elsewhere
+            events,
             mutable_text_under_cursor: _, // only used in eframe web
             ime,
             #[cfg(feature = "accesskit")]
@@ -236,35 +371,53 @@ impl WgpuLayerShellState {
             ..
         } = platform_output;
 
-        if let Some(ime) = ime {
-            self.set_ime_allowed(true);
-
-            let pixels_per_point =
-                pixels_per_point(self.egui_state.context(), self.scale_factor() as f32);
-            let ime_rect_px = pixels_per_point * ime.rect;
-            if self.egui_state.ime_rect_px != Some(ime_rect_px)
-                || self.egui_state.context().input(|i| !i.events.is_empty())
-            {
-                self.egui_state.ime_rect_px = Some(ime_rect_px);
-                self.set_ime_cursor_area(
-                    dpi::PhysicalPosition {
-                        x: ime_rect_px.min.x,
-                        y: ime_rect_px.min.y,
-                    }
-                    .into(),
-                    dpi::PhysicalSize {
-                        width: ime_rect_px.width(),
-                        height: ime_rect_px.height(),
-                    }
-                    .into(),
-                );
+        self.handle_output_commands(commands);
+
+        // Text input happens on the surfaces with keyboard focus.
+        let seats = self.seats_focused_on(id);
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return;
+        };
+
+        surface.egui_state.update_ime_text(&events);
+        surface.egui_state.ime_rect_px = ime.map(|ime| surface.pixels_per_point() * ime.rect);
+        if seats.is_empty() {
+            return;
+        }
+
+        let scale_factor = surface.scale_factor();
+        let desired = surface
+            .egui_state
+            .ime_rect_px
+            .filter(|_| self.ime_allowed)
+            .map(|rect| {
+                // The cursor rectangle is in surface-local coordinates of this surface.
+                let position = PhysicalPosition::new(rect.min.x, rect.min.y);
+                let size = PhysicalSize::new(rect.width(), rect.height());
+                let surrounding = surface
+                    .egui_state
+                    .ime_surrounding_text()
+                    .cloned()
+                    .unwrap_or_else(ImeSurroundingText::empty);
+                ImeRequestData::default()
+                    .with_hint_and_purpose(ImeHint::NONE, self.ime_purpose)
+                    .with_cursor_area(
+                        position.to_logical::<f64>(scale_factor).into(),
+                        size.to_logical::<f64>(scale_factor).into(),
+                    )
+                    .with_surrounding_text(surrounding)
+            });
+        for seat in seats {
+            let current = self.seat_map[&seat].text_input_state.as_ref();
+            if let Some(request) = ime_transition(current, desired.clone(), scale_factor) {
+                let _ = self.request_ime_update(&seat, request);
             }
-        } else {
-            self.egui_state.ime_rect_px = None;
         }
     }
 
-    /// Returns `true` if the requested state was applied.
+    /// Returns `true` if whether the IME is allowed changed.
+    ///
+    /// Disallowing disables the IME right away, the next frame editing text enables it again.
     pub fn set_ime_allowed(&mut self, allowed: bool) -> bool {
         if self.ime_allowed == allowed {
             return false;
@@ -272,62 +425,40 @@ impl WgpuLayerShellState {
 
         info!("set ime {}", allowed);
         self.ime_allowed = allowed;
-
-        let mut applied = false;
-        for text_input in &self.text_inputs {
-            applied = true;
-            if allowed {
-                text_input.enable();
-                text_input.set_content_type_by_purpose(self.ime_purpose);
-            } else {
-                text_input.disable();
+        if !allowed {
+            for seat in self.seats_with_ime() {
+                let _ = self.request_ime_update(&seat, ImeRequest::Disable);
             }
-            text_input.commit();
         }
-
-        applied
+        true
     }
 
-    pub fn set_passthrough(&mut self, pass: bool) {
+    pub fn set_passthrough(&mut self, id: SurfaceId, pass: bool) {
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return;
+        };
         if pass {
             let region = self
                 .compositor
                 .wl_compositor()
                 .create_region(&self.queue_handle, ());
-            self.layer.set_input_region(Some(&region));
+            surface.role.set_input_region(Some(&region));
         } else {
-            self.layer.set_input_region(None);
-        }
-        self.passthrough = pass;
-    }
-
-    pub fn set_layer_opts(&mut self) {
-        let options = &self.layer_opts;
-        let layer_surface = &self.layer;
-        if let Some(anchor) = options.anchor {
-            layer_surface.set_anchor(anchor);
-        }
-        if let Some(keyboard_interactivity) = options.keyboard_interactivity {
-            layer_surface.set_keyboard_interactivity(keyboard_interactivity);
+            surface.role.set_input_region(None);
         }
-        layer_surface.set_size(options.width, options.height);
-        layer_surface.set_opaque_region(None);
-        layer_surface.set_margin(
-            options.margin.0,
-            options.margin.1,
-            options.margin.2,
-            options.margin.3,
-        );
-        layer_surface.commit();
+        surface.passthrough = pass;
+        // Restored by the next frame in auto passthrough mode.
+        surface.input_region = None;
+        surface.egui_state.context().request_repaint();
     }
 
     pub(crate) fn new(
         loop_handle: LoopHandle<'static, Self>,
-        options: LayerShellOptions,
         ev: flume::Sender<WPEvent>,
-    ) -> Self {
-        let connection = Connection::connect_to_env().unwrap();
-        let (global_list, event_queue) = registry_queue_init(&connection).unwrap();
+        ev_rx: EvRx,
+    ) -> Result<Self, InitError> {
+        let connection = Connection::connect_to_env()?;
+        let (global_list, event_queue) = registry_queue_init(&connection)?;
         let queue_handle: Arc<QueueHandle<WgpuLayerShellState>> = Arc::new(event_queue.handle());
         let globals = &global_list;
 
@@ -339,262 +470,521 @@ impl WgpuLayerShellState {
         // TODO: Future support
         warn!("ExtBackgroundEffectManagerV1 {:?}", &bg_eft);
 
-        WaylandSource::new(connection.clone(), event_queue)
-            .insert(loop_handle.clone())
-            .unwrap();
-        let mut has_blur = false;
         let display = connection.display();
         display.get_registry(&queue_handle, ());
         let compositor_state = CompositorState::bind(&global_list, &queue_handle)
-            .expect("wl_compositor not available");
-        let wl_surface = compositor_state.create_surface(&queue_handle);
+            .map_err(|_| InitError::MissingGlobal("wl_compositor"))?;
 
         let kdeblur =
             global_list.bind::<OrgKdeKwinBlurManager, _, _>(queue_handle.as_ref(), 0..=1, ());
 
-        let layer_shell =
-            LayerShell::bind(&global_list, &queue_handle).expect("layer shell not available");
-        let layer = options.layer.unwrap_or(Layer::Top);
-        let layer_surface = layer_shell.create_layer_surface(
-            &queue_handle,
-            wl_surface,
-            layer,
-            Some(options.namespace.clone()),
-            None,
-        );
-
-        if let Some(anchor) = options.anchor {
-            layer_surface.set_anchor(anchor);
-        }
-        if let Some(keyboard_interactivity) = options.keyboard_interactivity {
-            layer_surface.set_keyboard_interactivity(keyboard_interactivity);
-        }
-        layer_surface.set_size(options.width, options.height);
-        layer_surface.set_opaque_region(None);
-        layer_surface.set_margin(
-            options.margin.0,
-            options.margin.1,
-            options.margin.2,
-            options.margin.3,
-        );
-        layer_surface.commit();
+        let layer_shell = LayerShell::bind(&global_list, &queue_handle)
+            .map_err(|_| InitError::MissingGlobal("zwlr_layer_shell_v1"))?;
 
         let vk_mgr =
             global_list.bind::<ZwpVirtualKeyboardManagerV1, _, _>(queue_handle.as_ref(), 0..=1, ());
         if vk_mgr.is_ok() {
             info!("zwp_virtual_keyboard_manager_v1 available");
         }
+        let input_method_manager = global_list
+            .bind::<ZwpInputMethodManagerV2, _, _>(queue_handle.as_ref(), 1..=1, ())
+            .ok();
 
         let seat_state = SeatState::new(globals, &queue_handle);
 
         let mut seats = AHashMap::default();
         for seat in seat_state.seats() {
-            seats.insert(seat.id(), PerSeat::default());
-        }
-
-        if let Ok(kdeblur) = kdeblur {
-            let region = compositor_state
-                .wl_compositor()
-                .create_region(&queue_handle, ());
-            let blur: OrgKdeKwinBlur =
-                kdeblur.create(layer_surface.wl_surface(), &queue_handle, ());
-            blur.set_region(Some(&region));
-            blur.commit();
-            has_blur = true;
+            seats.insert(seat.id(), PerSeat::new(seat));
         }
 
-        let wgpu_state = WgpuState::new(&connection.backend(), layer_surface.wl_surface())
-            .expect("Could not create wgpu state");
         let window_text_input_state = TextInputState::new(&global_list, &queue_handle).ok();
 
-        let egui_context = egui::Context::default();
-        let draw_request = Arc::new(RwLock::new(None));
-
-        egui_context.set_request_repaint_callback({
-            let draw_request = Arc::clone(&draw_request);
-            move |info| {
-                let mut draw_request = draw_request.write().unwrap();
-                *draw_request = Some(Instant::now() + info.delay);
-            }
+        let viewporter = global_list
+            .bind::<WpViewporter, _, _>(queue_handle.as_ref(), 1..=1, ())
+            .ok();
+        // Fractional scales can only be applied through a viewport.
+        let fractional_scale_manager = viewporter.as_ref().and_then(|_| {
+            global_list
+                .bind::<WpFractionalScaleManagerV1, _, _>(queue_handle.as_ref(), 1..=1, ())
+                .ok()
         });
-
-        let egui_state = egui_state::State::new(
-            egui_context,
-            &wgpu_state.device,
-            wgpu_state.surface_configuration.format,
-            None,
-            1,
+        info!(
+            "wp_viewporter {}, wp_fractional_scale_manager_v1 {}",
+            viewporter.is_some(),
+            fractional_scale_manager.is_some()
         );
+
         println!(
             "window_text_input_state {}",
             window_text_input_state.is_some()
         );
-        WgpuLayerShellState {
+        // Last, the source must not outlive a failed attempt to reconnect.
+        let wayland_token = WaylandSource::new(connection.clone(), event_queue)
+            .insert(loop_handle.clone())
+            .map_err(|e| InitError::EventSource(e.error))?;
+        Ok(WgpuLayerShellState {
             loop_handle: loop_handle.clone(),
+            wayland_token,
+            reconnecting: false,
+            reconnect_error: None,
+            connection,
             registry_state: RegistryState::new(&global_list),
             seat_state,
             output_state: OutputState::new(&global_list, &queue_handle),
 
-            exit: false,
-            layer: layer_surface,
-            current_layer: layer,
-            pointer: None,
-            keyboard: None,
-
-            has_frame_callback: false,
-            is_configured: false,
+            layer_shell,
+            wgpu_state: None,
+            surfaces: BTreeMap::new(),
+            active_surface: None,
+            keyboard_focus: None,
+            input_seat: None,
+            exit: None,
+            pointer_output: None,
 
             window_text_input_state,
-            text_input_state: None,
             queue_handle,
 
-            egui_state,
-            wgpu_state,
-            draw_request,
-
-            text_inputs: vec![],
             seat_map: seats,
             ime_purpose: ImePurpose::Normal,
             ime_allowed: true,
             compositor: compositor_state,
-            passthrough: false,
-            layer_opts: options,
+            viewporter,
+            fractional_scale_manager,
 
-            listentype: WlListenType::ListenOnSelect,
-            seat: None,
-            seat_name: None,
             data_manager: None,
-            data_device: None,
-            mime_types: Vec::new(),
-            set_priority: None,
-            pipereader: None,
-            current_type: None,
-            copy_data: None,
-            copy_cancelled: false,
+            primary_selection_manager: None,
+            mime_priority: Vec::new(),
+            selection_delivery: SelectionDelivery::default(),
+            owned_selections: AHashMap::default(),
+            selection_offers: AHashMap::default(),
+            selection_seats: AHashMap::default(),
+            clipboard_history: None,
+            data_device_manager: None,
             ev,
+            ev_rx,
+            subscribers: Vec::new(),
             ext_data_manager: None,
-            zwp_data_dev: None,
-            has_blur,
+            has_blur: kdeblur.is_ok(),
+            kde_blur: kdeblur.ok(),
             virtual_keyboard_manager: vk_mgr.ok(),
-            virtual_keyboard: None,
-        }
+            input_method_manager,
+            input_method_enabled: false,
+        })
     }
 
-    pub fn simulate_key(&mut self) {
-        todo!();
-        // this is errorneous impl
-        // It will probably be better to just implement an input method.
-        // Rather than using time on this.
-        
-        if let (Some(mgr), Some(seat)) =
-            (self.virtual_keyboard_manager.as_ref(), self.seat.as_ref())
-        {
-            if self.virtual_keyboard.is_none() {
-                let vk = mgr.create_virtual_keyboard(seat, &self.queue_handle, ());
-                self.virtual_keyboard = Some(vk);
-            }
+    /// Creates the wl_surface and its layer role according to `options`.
+    ///
+    /// Also returns the output the surface was placed on, if one was selected.
+    fn create_layer(
+        &self,
+        options: &LayerShellOptions,
+    ) -> (LayerSurface, Option<wl_output::WlOutput>) {
+        let output = options
+            .output
+            .as_ref()
+            .and_then(|selector| self.resolve_output(selector));
+        let wl_surface = self.compositor.create_surface(&self.queue_handle);
+        let layer_surface = self.layer_shell.create_layer_surface(
+            &self.queue_handle,
+            wl_surface,
+            options.layer.unwrap_or(Layer::Top),
+            Some(options.namespace.clone()),
+            output.as_ref(),
+        );
+        options.apply(&layer_surface);
+
+        if let Some(kdeblur) = &self.kde_blur {
+            let region = self
+                .compositor
+                .wl_compositor()
+                .create_region(&self.queue_handle, ());
+            let blur: OrgKdeKwinBlur =
+                kdeblur.create(layer_surface.wl_surface(), &self.queue_handle, ());
+            blur.set_region(Some(&region));
+            blur.commit();
+        }
+
+        (layer_surface, output)
+    }
 
-            if let Some(vk) = &self.virtual_keyboard {
-                // Example: press and release a key (keycode 30) — adjust as needed.
-                let _ = vk.key(0, 30, 1);
-                let _ = vk.key(0, 30, 0);
+    fn create_wgpu_surface(
+        &mut self,
+        wl_surface: &wl_surface::WlSurface,
+    ) -> Result<WgpuSurface, WgpuStateError> {
+        match &self.wgpu_state {
+            Some(wgpu_state) => wgpu_state.create_surface(&self.connection.backend(), wl_surface),
+            None => {
+                let (wgpu_state, wgpu_surface) =
+                    WgpuState::new(&self.connection.backend(), wl_surface)?;
+                self.wgpu_state = Some(wgpu_state);
+                Ok(wgpu_surface)
             }
-        } else {
-            warn!("virtual keyboard manager or seat not available");
         }
     }
 
-    pub fn set_margin(&mut self, margin: (i32, i32, i32, i32)) {
-        self.layer
-            .set_margin(margin.0, margin.1, margin.2, margin.3);
-        self.layer.commit();
+    /// Creates a layer surface driven by its own app. Messages sent to `msg` are applied to it.
+    pub(crate) fn add_surface(
+        &mut self,
+        id: SurfaceId,
+        options: LayerShellOptions,
+        app_creator: AppCreator,
+        (msg, channel): (MsgQueue, Channel<Msg>),
+    ) -> Result<(), InitError> {
+        let (layer, output) = self.create_layer(&options);
+        self.insert_surface(
+            id,
+            SurfaceRole::Layer(layer),
+            output,
+            options,
+            app_creator,
+            (msg, channel),
+        )
     }
 
-    //fn request_redraw(&self, )
+    /// Creates an input popup driven by its own app, shown by the compositor at the text
+    /// cursor while the input method of `seat`, or of the first seat, is active.
+    ///
+    /// Requires acting as the input method, see [`WgpuLayerShellState::set_input_method`].
+    /// Only `width`, `height`, `auto_size` and the transitions of `options` apply.
+    pub(crate) fn add_input_popup(
+        &mut self,
+        id: SurfaceId,
+        seat: Option<&str>,
+        options: LayerShellOptions,
+        app_creator: AppCreator,
+        (msg, channel): (MsgQueue, Channel<Msg>),
+    ) -> Result<(), InitError> {
+        let popup = self
+            .create_input_popup(id, seat)
+            .ok_or(InitError::NoInputMethod)?;
+        self.insert_surface(
+            id,
+            SurfaceRole::InputPopup(popup),
+            None,
+            options,
+            app_creator,
+            (msg, channel),
+        )?;
+        self.configure_input_popup(id);
+        Ok(())
+    }
 
-    pub(crate) fn should_draw(&mut self) -> bool {
-        if !self.has_frame_callback {
-            return false;
+    /// Input popups are sized by their buffer, they are never configured by the compositor.
+    fn configure_input_popup(&mut self, id: SurfaceId) {
+        let (Some(wgpu_state), Some(surface)) = (&self.wgpu_state, self.surfaces.get_mut(&id))
+        else {
+            return;
+        };
+        surface.logical_size = (
+            surface.layer_opts.width.max(1),
+            surface.layer_opts.height.max(1),
+        );
+        surface.apply_size(&wgpu_state.device);
+        surface.is_configured = true;
+        if !surface.is_unmapped() {
+            surface.start_drawing();
         }
+    }
+
+    fn insert_surface(
+        &mut self,
+        id: SurfaceId,
+        role: SurfaceRole,
+        output: Option<wl_output::WlOutput>,
+        options: LayerShellOptions,
+        app_creator: AppCreator,
+        (msg, channel): (MsgQueue, Channel<Msg>),
+    ) -> Result<(), InitError> {
+        let wgpu_surface = self.create_wgpu_surface(role.wl_surface())?;
+        let wgpu_state = self.wgpu_state.as_ref().unwrap();
+
+        let egui_state = egui_state::State::new(
+            egui::Context::default(),
+            &wgpu_state.device,
+            wgpu_surface.surface_configuration.format,
+            None,
+            1,
+        );
+        let mut surface =
+            PopupSurface::new(id, role, wgpu_surface, egui_state, options, self.ev.clone());
+        surface.output = output;
+        if let Some(output) = surface.output.as_ref().and_then(|o| self.output_state.info(o)) {
+            // Until the surface reports its preferred scale.
+            surface.scale_factor = output.scale_factor as f64;
+        }
+
+        surface.msg_token = Some(
+            self.loop_handle
+                .insert_source(channel, move |e, _, state: &mut WgpuLayerShellState| {
+                    if let calloop::channel::Event::Msg(m) = e {
+                        info!("{:?} {:?}", id, &m);
+                        handle_msg(state, id, m);
+                    }
+                })
+                .map_err(|e| InitError::EventSource(e.error))?,
+        );
+
+        let ctx = surface.egui_state.context().clone();
+        let app = app_creator(&ctx, msg, self.subscribe());
+        let app = match app {
+            Ok(app) => app,
+            Err(e) => {
+                if let Some(token) = surface.msg_token.take() {
+                    self.loop_handle.remove(token);
+                }
+                return Err(InitError::App(e));
+            }
+        };
+        self.surfaces.insert(id, surface);
+        self.attach_scale_objects(id);
+
+        self.active_surface = Some(id);
+        app.init(&ctx, self);
+        self.active_surface = None;
+        self.surfaces.get_mut(&id).unwrap().app = Some(app);
+
+        Ok(())
+    }
 
-        if !self.egui_state.input().events.is_empty() {
-            return true;
+    /// Destroys a surface and drops its app.
+    pub fn remove_surface(&mut self, id: SurfaceId) {
+        let Some(mut surface) = self.surfaces.remove(&id) else {
+            return;
+        };
+        for seat in self.seats_focused_on(id) {
+            self.set_seat_focus(&seat, None);
+        }
+        // The surface may be removed from within its own message callback.
+        if let Some(token) = surface.msg_token.take() {
+            self.loop_handle
+                .insert_idle(move |state| state.loop_handle.remove(token));
+        }
+        if let Some(mut app) = surface.app.take() {
+            app.on_exit();
+        }
+        surface.emit(SurfaceEvent::Destroyed);
+        if self.surfaces.is_empty() && self.exit.is_none() {
+            // Nothing is left to send messages to the loop through.
+            self.exit = Some(ExitReason::AllSurfacesClosed);
         }
+    }
 
-        match *self.draw_request.read().unwrap() {
-            Some(time) => time <= Instant::now(),
-            None => false,
+    /// Destroys all surfaces, letting their apps know through [`crate::App::on_exit`].
+    pub(crate) fn close_all(&mut self) {
+        let ids: Vec<_> = self.surfaces.keys().copied().collect();
+        for id in ids {
+            self.remove_surface(id);
+        }
+        if let Err(e) = self.connection.flush() {
+            warn!("could not flush the destroyed surfaces: {}", e);
         }
     }
 
-    pub(crate) fn get_timeout(&self) -> Option<Duration> {
-        match *self.draw_request.read().unwrap() {
-            Some(instant) => {
-                if self.has_frame_callback {
-                    Some(instant.duration_since(Instant::now()))
-                } else {
-                    None
+    /// Re-creates layer surfaces that were closed by the compositor or have to move, keeping
+    /// egui and app state.
+    pub(crate) fn recreate_surfaces(&mut self) {
+        let pending: Vec<_> = self
+            .surfaces
+            .values()
+            // An unmapped surface is re-created once it is shown again.
+            .filter(|s| s.recreate && !s.is_unmapped())
+            .map(|s| s.id())
+            .collect();
+
+        for id in pending {
+            info!("re-creating layershell of {:?}", id);
+            let surface = &self.surfaces[&id];
+            let options = surface.layer_opts.clone();
+            let (role, output) = match surface.role.input_popup() {
+                Some(popup) => {
+                    // Retried until the input method of a new connection is there.
+                    let Some(popup) = self.create_input_popup(id, popup.seat.as_deref()) else {
+                        continue;
+                    };
+                    (SurfaceRole::InputPopup(popup), None)
+                }
+                None => {
+                    let (layer, output) = self.create_layer(&options);
+                    (SurfaceRole::Layer(layer), output)
                 }
+            };
+            let wgpu_surface = match self.create_wgpu_surface(role.wl_surface()) {
+                Ok(wgpu_surface) => wgpu_surface,
+                Err(e) => {
+                    warn!("could not re-create the swapchain of {:?}: {}", id, e);
+                    continue;
+                }
+            };
+
+            let surface = self.surfaces.get_mut(&id).unwrap();
+            surface.release_scale_objects();
+            surface.wgpu_surface = wgpu_surface;
+            surface.role = role;
+            surface.output = output;
+            surface.entered_outputs.clear();
+            surface.current_layer = options.layer.unwrap_or(Layer::Top);
+            if let Some(layer) = surface.role.layer().filter(|_| surface.hidden) {
+                surface.current_layer = Layer::Background;
+                layer.set_layer(Layer::Background);
+                layer.commit();
+            }
+            surface.has_frame_callback = false;
+            surface.is_configured = false;
+            surface.recreate = false;
+            surface.auto_size_requested = None;
+            let passthrough = surface.passthrough;
+            let ctx = surface.egui_state.context().clone();
+            let app = surface.app.take();
+
+            self.set_passthrough(id, passthrough);
+            self.attach_scale_objects(id);
+            if self.surfaces[&id].role.input_popup().is_some() {
+                self.configure_input_popup(id);
             }
-            None => None,
+            if let Some(app) = app {
+                self.active_surface = Some(id);
+                app.init(&ctx, self);
+                self.active_surface = None;
+                self.surfaces.get_mut(&id).unwrap().app = Some(app);
+            }
+            self.surfaces[&id].emit(SurfaceEvent::Recreated);
         }
     }
 
-    pub(crate) fn draw(&mut self, application: &mut dyn App) {
-        *self.draw_request.write().unwrap() = None;
-        self.has_frame_callback = false;
-        // crates/eframe/src/native/wgpu_integration.rs
+    pub fn surface(&self, id: SurfaceId) -> Option<&PopupSurface> {
+        self.surfaces.get(&id)
+    }
 
-        application.sync(&self);
-        let full_output = self
-            .egui_state
-            .process_events(|ctx| application.update(ctx));
+    pub fn surface_mut(&mut self, id: SurfaceId) -> Option<&mut PopupSurface> {
+        self.surfaces.get_mut(&id)
+    }
+
+    pub fn surfaces(&self) -> impl Iterator<Item = &PopupSurface> {
+        self.surfaces.values()
+    }
+
+    /// The surface whose [`App`] is currently being synced or initialized.
+    pub fn current_surface(&self) -> Option<&PopupSurface> {
+        self.active_surface.and_then(|id| self.surfaces.get(&id))
+    }
 
-        let surface_texture = self
-            .wgpu_state
-            .surface
-            .get_current_texture()
-            .expect("Failed to acquire next swap chain texture");
+    pub(crate) fn surface_id_of(&self, wl_surface: &wl_surface::WlSurface) -> Option<SurfaceId> {
+        self.surfaces
+            .values()
+            .find(|s| s.role.wl_surface() == wl_surface)
+            .map(|s| s.id())
+    }
+
+    pub(crate) fn surface_of_mut(
+        &mut self,
+        wl_surface: &wl_surface::WlSurface,
+    ) -> Option<&mut PopupSurface> {
+        self.surfaces
+            .values_mut()
+            .find(|s| s.role.wl_surface() == wl_surface)
+    }
+
+    //fn request_redraw(&self, )
+
+    pub(crate) fn get_timeout(&self) -> Option<Duration> {
+        self.surfaces
+            .values()
+            .filter_map(|s| s.draw_deadline())
+            .min()
+            .map(|instant| instant.duration_since(Instant::now()))
+    }
+
+    /// Draws every surface that has a pending repaint and a frame callback.
+    pub(crate) fn draw_pending(&mut self) {
+        let ids: Vec<_> = self.surfaces.keys().copied().collect();
+        for id in ids {
+            if self.surfaces.get_mut(&id).is_some_and(|s| s.should_draw()) {
+                self.draw(id);
+            }
+        }
+    }
+
+    pub(crate) fn draw(&mut self, id: SurfaceId) {
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return;
+        };
+        let Some(mut application) = surface.app.take() else {
+            return;
+        };
+        *surface.draw_request.write().unwrap() = None;
+        surface.has_frame_callback = false;
+        // crates/eframe/src/native/wgpu_integration.rs
+
+        self.active_surface = Some(id);
+        application.sync(self);
+        self.active_surface = None;
+
+        let surface = self.surfaces.get_mut(&id).unwrap();
+        let pixels_per_point = surface.pixels_per_point();
+        let wgpu_state = self.wgpu_state.as_ref().unwrap();
+        let mut content_size = None;
+        let full_output = surface.egui_state.process_events(|ctx| {
+            application.update(ctx);
+            content_size = Some(auto_size::content_size(ctx));
+        });
+        if let Some(content_size) = content_size {
+            surface.auto_size(content_size, &wgpu_state.device);
+        }
+        surface.update_input_region(&self.compositor, &self.queue_handle);
+        surface.app = Some(application);
+
+        let surface_texture = match surface.wgpu_surface.surface.get_current_texture() {
+            Ok(surface_texture) => surface_texture,
+            Err(err) => {
+                // Lost with the compositor connection, try again with the next frame.
+                warn!("could not acquire the next swapchain texture: {}", err);
+                surface.has_frame_callback = true;
+                surface.egui_state.context().request_repaint();
+                return;
+            }
+        };
 
         let surface_view = surface_texture
             .texture
             .create_view(&egui_wgpu::wgpu::TextureViewDescriptor::default());
 
-        let mut encoder = self
-            .wgpu_state
+        let mut encoder = wgpu_state
             .device
             .create_command_encoder(&egui_wgpu::wgpu::CommandEncoderDescriptor { label: None });
 
         let screen_descriptor = ScreenDescriptor {
             size_in_pixels: [
-                self.wgpu_state.surface_configuration.width,
-                self.wgpu_state.surface_configuration.height,
+                surface.wgpu_surface.surface_configuration.width,
+                surface.wgpu_surface.surface_configuration.height,
             ],
-            pixels_per_point: self.pixels_per_point(),
+            pixels_per_point,
         };
 
-        self.egui_state.draw(
-            &self.wgpu_state.device,
-            &self.wgpu_state.queue,
+        let (alpha, transform) = surface.transition_frame();
+        surface.egui_state.set_frame_transform(alpha, transform);
+        surface.egui_state.draw(
+            &wgpu_state.device,
+            &wgpu_state.queue,
             &mut encoder,
             &surface_view,
             screen_descriptor,
             full_output.shapes,
             full_output.textures_delta,
         );
-        self.wgpu_state.queue.submit(Some(encoder.finish()));
+        wgpu_state.queue.submit(Some(encoder.finish()));
 
-        self.layer
+        surface
+            .role
             .wl_surface()
-            .frame(&self.queue_handle, self.layer.wl_surface().clone());
+            .frame(&self.queue_handle, surface.role.wl_surface().clone());
         surface_texture.present();
+        surface.advance_transition();
 
         // crates/egui-winit/src/lib.rs
 
-        self.handle_platform(full_output.platform_output);
+        self.handle_platform(id, full_output.platform_output);
 
         if false {
-            let pixels_per_point = self.pixels_per_point();
             for (id, view) in full_output.viewport_output {
                 for cmd in view.commands {
                     match cmd {
@@ -636,46 +1026,23 @@ impl ProvidesRegistryState for WgpuLayerShellState {
     registry_handlers![OutputState];
 }
 
-delegate_output!(WgpuLayerShellState);
-impl OutputHandler for WgpuLayerShellState {
-    fn output_state(&mut self) -> &mut OutputState {
-        &mut self.output_state
-    }
-
-    fn new_output(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
-    ) {
-    }
-
-    fn update_output(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
-    ) {
-    }
-
-    fn output_destroyed(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
-    ) {
-    }
-}
-
 delegate_compositor!(WgpuLayerShellState);
 impl CompositorHandler for WgpuLayerShellState {
     fn scale_factor_changed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_factor: i32,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
     ) {
+        // wp_fractional_scale_v1 takes precedence when it's available.
+        let Some(surface) = self.surface_of_mut(surface) else {
+            return;
+        };
+        if surface.fractional_scale.is_none() {
+            let id = surface.id();
+            self.set_surface_scale(id, new_factor as f64);
+        }
     }
 
     fn transform_changed(
@@ -691,56 +1058,86 @@ impl CompositorHandler for WgpuLayerShellState {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
+        surface: &wl_surface::WlSurface,
         _time: u32,
     ) {
-        self.has_frame_callback = true;
+        if let Some(surface) = self.surface_of_mut(surface) {
+            // Callbacks requested before unmapping may still fire.
+            surface.has_frame_callback = !surface.is_unmapped();
+        }
     }
 
     fn surface_enter(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _output: &wl_output::WlOutput,
+        surface: &wl_surface::WlSurface,
+        output: &wl_output::WlOutput,
     ) {
+        let name = self.output_state.info(output).and_then(|info| info.name);
+        if let Some(surface) = self.surface_of_mut(surface) {
+            surface.entered_outputs.push(output.clone());
+            surface.emit(SurfaceEvent::OutputEntered(name));
+        }
     }
 
     fn surface_leave(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _output: &wl_output::WlOutput,
+        surface: &wl_surface::WlSurface,
+        output: &wl_output::WlOutput,
     ) {
+        let name = self.output_state.info(output).and_then(|info| info.name);
+        if let Some(surface) = self.surface_of_mut(surface) {
+            surface.entered_outputs.retain(|o| o != output);
+            surface.emit(SurfaceEvent::OutputLeft(name));
+        }
     }
 }
 
 delegate_layer!(WgpuLayerShellState);
 impl LayerShellHandler for WgpuLayerShellState {
-    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
-        self.exit = true;
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        if let Some(surface) = self.surface_of_mut(layer.wl_surface()) {
+            // For some reason the layer get destroyed externally. Usually after resuming from computer suspension.
+            warn!("layershell of {:?} exited. restarting..", surface.id());
+            surface.recreate = true;
+            surface.emit(SurfaceEvent::Closed);
+        }
     }
 
     fn configure(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _layer: &LayerSurface,
+        layer: &LayerSurface,
         configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        if !self.is_configured {
-            self.is_configured = true;
-            self.has_frame_callback = true;
-            *self.draw_request.write().unwrap() = Some(Instant::now());
-        }
+        let Some(wgpu_state) = self.wgpu_state.as_ref() else {
+            return;
+        };
+        let Some(surface) = self
+            .surfaces
+            .values_mut()
+            .find(|s| s.role.wl_surface() == layer.wl_surface())
+        else {
+            return;
+        };
 
-        self.wgpu_state
-            .resize(configure.new_size.0, configure.new_size.1);
+        if !surface.is_configured {
+            surface.is_configured = true;
+            if !surface.is_unmapped() {
+                surface.has_frame_callback = true;
+                *surface.draw_request.write().unwrap() = Some(Instant::now());
+            }
+        }
 
-        self.egui_state
-            .set_size(configure.new_size.0, configure.new_size.1);
+        surface.logical_size = configure.new_size;
+        surface.apply_size(&wgpu_state.device);
+        let (width, height) = configure.new_size;
+        surface.emit(SurfaceEvent::Configured { width, height });
     }
 }
 delegate_seat!(WgpuLayerShellState);
@@ -750,7 +1147,8 @@ impl SeatHandler for WgpuLayerShellState {
     }
 
     fn new_seat(&mut self, _: &Connection, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
-        self.seat_map.insert(seat.id(), Default::default());
+        self.seat_map.insert(seat.id(), PerSeat::new(seat));
+        self.create_data_devices();
     }
 
     fn new_capability(
@@ -769,23 +1167,28 @@ impl SeatHandler for WgpuLayerShellState {
         };
 
         match capability {
-            Capability::Pointer if self.pointer.is_none() => {
+            Capability::Pointer if seat_state.pointer.is_none() => {
                 let pointer = self
                     .seat_state
                     .get_pointer(qh, &seat)
                     .expect("Failed to create pointer");
-                self.pointer = Some(pointer);
+                seat_state.pointer = Some(pointer);
             }
-            Capability::Keyboard if self.keyboard.is_none() => {
-                self.keyboard = Some(
+            Capability::Keyboard if seat_state.keyboard.is_none() => {
+                seat_state.keyboard = Some(
                     self.seat_state
                         .get_keyboard_with_repeat(
                             qh,
                             &seat,
                             None,
                             self.loop_handle.clone(),
-                            Box::new(|state, _wl_kbd, event| {
-                                handle_key_press(event, true, &mut state.egui_state.input());
+                            Box::new(|state, wl_kbd, event| {
+                                let Some(seat) = state.seat_of_keyboard(wl_kbd) else {
+                                    return;
+                                };
+                                if let Some(input) = state.focused_input(&seat) {
+                                    handle_key_press(event, true, input);
+                                }
                             }),
                         )
                         .expect("Failed to create keyboard"),
@@ -806,6 +1209,7 @@ impl SeatHandler for WgpuLayerShellState {
                 TextInputData::default(),
             )));
         }
+        self.create_input_method(&seat.id());
     }
 
     fn remove_capability(
@@ -815,20 +1219,70 @@ impl SeatHandler for WgpuLayerShellState {
         seat: wl_seat::WlSeat,
         capability: Capability,
     ) {
+        let Some(seat_state) = self.seat_map.get_mut(&seat.id()) else {
+            return;
+        };
         match capability {
-            Capability::Pointer if self.pointer.is_some() => {
-                self.pointer.take().unwrap().release();
+            Capability::Pointer if seat_state.pointer.is_some() => {
+                seat_state.pointer.take().unwrap().release();
             }
-            Capability::Keyboard if self.keyboard.is_some() => {
-                self.keyboard.take().unwrap().release();
+            Capability::Keyboard if seat_state.keyboard.is_some() => {
+                seat_state.keyboard.take().unwrap().release();
+                self.set_seat_focus(&seat.id(), None);
             }
             _ => {}
         }
     }
 
     fn remove_seat(&mut self, _: &Connection, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
-        self.seat_map.remove(&seat.id());
+        self.set_seat_focus(&seat.id(), None);
+        if let Some(seat_state) = self.seat_map.remove(&seat.id()) {
+            seat_state.release();
+        }
     }
 }
 
 // delegate_dispatch!(WgpuLayerShellState: [ExtBackgroundEffectManagerV1: ()] => WgpuLayerShellState);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HORIZONTAL: Anchor = Anchor::LEFT.union(Anchor::RIGHT);
+    const VERTICAL: Anchor = Anchor::TOP.union(Anchor::BOTTOM);
+
+    #[test]
+    fn exclusive_edges() {
+        let cases = [
+            (Anchor::TOP, Some(Anchor::TOP)),
+            (Anchor::TOP | HORIZONTAL, Some(Anchor::TOP)),
+            (Anchor::BOTTOM | HORIZONTAL, Some(Anchor::BOTTOM)),
+            (Anchor::LEFT | VERTICAL, Some(Anchor::LEFT)),
+            (Anchor::RIGHT, Some(Anchor::RIGHT)),
+            (Anchor::empty(), None),
+            (Anchor::TOP | Anchor::LEFT, None),
+            (VERTICAL, None),
+            (Anchor::all(), None),
+        ];
+        for (anchor, edge) in cases {
+            assert_eq!(exclusive_edge(anchor), edge, "{:?}", anchor);
+        }
+    }
+
+    #[test]
+    fn panels_stretch_along_their_edge() {
+        let cases = [
+            (Anchor::TOP, HORIZONTAL, (0, 30)),
+            (Anchor::BOTTOM, HORIZONTAL, (0, 30)),
+            (Anchor::LEFT, VERTICAL, (30, 0)),
+            (Anchor::RIGHT, VERTICAL, (30, 0)),
+        ];
+        for (edge, across, size) in cases {
+            let panel = LayerShellOptions::panel(edge, 30);
+            assert_eq!(panel.anchor, Some(edge | across), "{:?}", edge);
+            assert_eq!((panel.width, panel.height), size, "{:?}", edge);
+            assert_eq!(panel.exclusive_zone, Some(30));
+            assert_eq!(exclusive_edge(edge | across), Some(edge));
+        }
+    }
+}
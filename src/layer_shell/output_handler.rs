@@ -0,0 +1,128 @@
+use sctk::{
+    delegate_output,
+    output::{OutputHandler, OutputInfo, OutputState},
+};
+use wayland_client::{protocol::wl_output, Connection, QueueHandle};
+
+use super::WgpuLayerShellState;
+
+/// Picks the output a layer surface is placed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputSelector {
+    /// Match the `wl_output` name, e.g. `DP-1`.
+    Name(String),
+    /// Match the `wl_output` description.
+    Description(String),
+    /// Wayland has no notion of a primary output, this is the first one advertised.
+    Primary,
+    /// The output the pointer was last seen on, through any surface of this app. Surfaces
+    /// move along when it enters one on another output. Before that the compositor chooses,
+    /// usually the focused output.
+    ///
+    /// The pointer position is not mapped to an output: the first output the entered
+    /// surface is shown on stands in for it, which is only off for a surface spanning
+    /// several outputs.
+    UnderPointer,
+}
+
+impl OutputSelector {
+    fn matches(&self, info: &OutputInfo) -> bool {
+        match self {
+            OutputSelector::Name(name) => info.name.as_ref() == Some(name),
+            OutputSelector::Description(desc) => info.description.as_ref() == Some(desc),
+            OutputSelector::Primary | OutputSelector::UnderPointer => false,
+        }
+    }
+}
+
+impl WgpuLayerShellState {
+    /// Outputs currently advertised by the compositor, with names, modes and scales.
+    pub fn outputs(&self) -> Vec<OutputInfo> {
+        self.output_state
+            .outputs()
+            .filter_map(|o| self.output_state.info(&o))
+            .collect()
+    }
+
+    /// Returns `None` when nothing matches, leaving the choice to the compositor.
+    pub(crate) fn resolve_output(&self, selector: &OutputSelector) -> Option<wl_output::WlOutput> {
+        match selector {
+            OutputSelector::Primary => self.output_state.outputs().next(),
+            OutputSelector::UnderPointer => self.pointer_output.clone(),
+            _ => self.output_state.outputs().find(|o| {
+                self.output_state
+                    .info(o)
+                    .is_some_and(|info| selector.matches(&info))
+            }),
+        }
+    }
+
+    /// Re-create surfaces whose selector now resolves to a different output than the one they
+    /// were placed on, e.g. after the requested monitor got plugged in or the pointer moved
+    /// to another output.
+    pub(crate) fn replace_misplaced(&mut self) {
+        let misplaced: Vec<_> = self
+            .surfaces
+            .values()
+            .filter(|s| match &s.layer_opts.output {
+                None => false,
+                // Stays where the compositor put it until the pointer is seen.
+                Some(OutputSelector::UnderPointer) => {
+                    self.pointer_output.is_some() && self.pointer_output != s.output
+                }
+                Some(selector) => self.resolve_output(selector) != s.output,
+            })
+            .map(|s| s.id())
+            .collect();
+
+        for id in misplaced {
+            if let Some(surface) = self.surfaces.get_mut(&id) {
+                surface.recreate = true;
+            }
+        }
+    }
+}
+
+delegate_output!(WgpuLayerShellState);
+impl OutputHandler for WgpuLayerShellState {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _output: wl_output::WlOutput,
+    ) {
+        self.replace_misplaced();
+    }
+
+    fn update_output(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _output: wl_output::WlOutput,
+    ) {
+        // Names and descriptions may only arrive with the first update.
+        self.replace_misplaced();
+    }
+
+    fn output_destroyed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        if self.pointer_output.as_ref() == Some(&output) {
+            self.pointer_output = None;
+        }
+        for surface in self.surfaces.values_mut() {
+            surface.entered_outputs.retain(|o| o != &output);
+            if surface.output.as_ref() == Some(&output) {
+                surface.output = None;
+                surface.recreate = true;
+            }
+        }
+    }
+}
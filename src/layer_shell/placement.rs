@@ -0,0 +1,118 @@
+use sctk::shell::wlr_layer::Anchor;
+
+use super::{OutputSelector, SurfaceId, WgpuLayerShellState};
+
+/// Distance kept between the requested point and the popup, in logical pixels.
+const POINT_GAP: i32 = 8;
+/// Size along an axis the surface asked to be stretched on, which anchoring to a corner
+/// doesn't allow.
+const FALLBACK_SIZE: u32 = 200;
+
+/// Places a `size` popup next to `point` inside an `output` sized area.
+///
+/// The popup goes below and to the right of the point, flips to the other side when it would
+/// overflow, and is clamped to the output when it fits on neither side.
+/// Returns the top-left corner.
+pub fn place_near(point: (i32, i32), size: (u32, u32), output: (i32, i32)) -> (i32, i32) {
+    fn axis(at: i32, len: u32, bound: i32) -> i32 {
+        let len = len as i32;
+        let after = at + POINT_GAP;
+        let before = at - POINT_GAP - len;
+        let pos = if after + len <= bound || before < 0 {
+            after
+        } else {
+            before
+        };
+        pos.min(bound - len).max(0)
+    }
+
+    (
+        axis(point.0, size.0, output.0),
+        axis(point.1, size.1, output.1),
+    )
+}
+
+impl WgpuLayerShellState {
+    /// Moves the surface next to `(x, y)`, given in logical pixels relative to the top-left
+    /// corner of the output, and shows it if hidden.
+    ///
+    /// Input popups are placed by the compositor and are left alone.
+    pub fn show_at(&mut self, id: SurfaceId, x: i32, y: i32, output: Option<OutputSelector>) {
+        let Some(surface) = self.surfaces.get(&id).filter(|s| s.role.layer().is_some()) else {
+            return;
+        };
+        let target = match &output {
+            Some(selector) => self.resolve_output(selector),
+            None => surface
+                .output
+                .clone()
+                .or_else(|| surface.entered_outputs.first().cloned())
+                .or_else(|| self.resolve_output(&OutputSelector::Primary)),
+        };
+        let output_size = target
+            .as_ref()
+            .and_then(|o| self.output_state.info(o))
+            .and_then(|info| info.logical_size)
+            .unwrap_or((i32::MAX, i32::MAX));
+        let size_along = |current: u32, requested: u32| match (current, requested) {
+            (0, 0) => FALLBACK_SIZE,
+            (0, requested) => requested,
+            (current, _) => current,
+        };
+        let size = (
+            size_along(surface.logical_size.0, surface.layer_opts.width),
+            size_along(surface.logical_size.1, surface.layer_opts.height),
+        );
+        let moves = output.is_some() && target != surface.output;
+
+        let (left, top) = place_near((x, y), size, output_size);
+        let surface = self.surfaces.get_mut(&id).unwrap();
+        surface.layer_opts.anchor = Some(Anchor::TOP | Anchor::LEFT);
+        surface.layer_opts.margin = (top, 0, 0, left);
+        (surface.layer_opts.width, surface.layer_opts.height) = size;
+        // Relative to the output, not to the area left by panels.
+        surface.layer_opts.exclusive_zone = Some(-1);
+        if moves {
+            surface.layer_opts.output = output;
+            surface.recreate = true;
+            surface.set_hidden(false);
+            return;
+        }
+
+        if let Some(layer) = surface.role.layer() {
+            layer.set_anchor(Anchor::TOP | Anchor::LEFT);
+            layer.set_size(size.0, size.1);
+            layer.set_exclusive_zone(-1);
+        }
+        // Commits the changes above too.
+        surface.set_margin(surface.layer_opts.margin);
+        surface.set_hidden(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: (i32, i32) = (1000, 800);
+
+    #[test]
+    fn goes_below_right_of_point() {
+        assert_eq!(place_near((100, 100), (200, 50), OUTPUT), (108, 108));
+    }
+
+    #[test]
+    fn flips_when_overflowing() {
+        assert_eq!(place_near((900, 780), (200, 50), OUTPUT), (692, 722));
+    }
+
+    #[test]
+    fn clamps_when_fitting_on_neither_side() {
+        assert_eq!(place_near((100, 400), (200, 700), OUTPUT), (108, 100));
+    }
+
+    #[test]
+    fn sticks_to_the_origin_when_larger_than_output() {
+        assert_eq!(place_near((500, 400), (1200, 900), OUTPUT), (0, 0));
+    }
+}
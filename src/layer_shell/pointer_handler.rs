@@ -3,6 +3,7 @@ use egui::{PointerButton, Vec2};
 use sctk::{
     delegate_pointer,
     seat::pointer::{PointerEvent, PointerEventKind, PointerHandler},
+    shell::WaylandSurface,
 };
 use tracing::info;
 use wayland_client::{
@@ -11,7 +12,7 @@ use wayland_client::{
     Connection, QueueHandle,
 };
 
-use crate::layer_shell::pixels_per_point;
+use crate::application::SurfaceEvent;
 
 use super::WgpuLayerShellState;
 
@@ -25,10 +26,42 @@ impl PointerHandler for WgpuLayerShellState {
         wl: &wl_pointer::WlPointer,
         events: &[PointerEvent],
     ) {
+        let seat_id = self.seat_of_pointer(wl);
+        let seat = seat_id.as_ref().and_then(|seat| self.seat_name(seat));
+        // Held on the keyboard of the same seat.
+        let modifiers = seat_id
+            .as_ref()
+            .and_then(|seat| self.seat_map.get(seat))
+            .map(|per_seat| per_seat.modifiers)
+            .unwrap_or_default();
+        let pointer_output = self.pointer_output.clone();
+        let mut pressed = None;
         for event in events {
             // let position: PhysicalPosition<f64> =
             //     LogicalPosition::new(event.position.0, event.position.1).to_physical(self.scale_factor());
-            let position = egui::pos2(event.position.0 as f32, event.position.1 as f32);
+            let Some(surface) = self
+                .surfaces
+                .values_mut()
+                .find(|s| s.role.wl_surface() == &event.surface)
+            else {
+                continue;
+            };
+            match event.kind {
+                PointerEventKind::Enter { .. } => {
+                    if let Some(output) = surface.entered_outputs.first() {
+                        self.pointer_output = Some(output.clone());
+                    }
+                    surface.emit(SurfaceEvent::PointerEntered { seat: seat.clone() });
+                }
+                PointerEventKind::Leave { .. } => {
+                    surface.emit(SurfaceEvent::PointerLeft { seat: seat.clone() })
+                }
+                PointerEventKind::Press { serial, .. } => pressed = Some(serial),
+                _ => {}
+            }
+            let position = surface
+                .egui_state
+                .logical_to_points(event.position.0, event.position.1);
             let egui_event = match event.kind {
                 PointerEventKind::Enter { .. } => egui::Event::PointerMoved(position),
                 PointerEventKind::Motion { .. } => egui::Event::PointerMoved(position),
@@ -38,7 +71,7 @@ impl PointerHandler for WgpuLayerShellState {
                     if let Some(button) = translate_button(button) {
                         egui::Event::PointerButton {
                             button,
-                            modifiers: self.egui_state.modifiers(),
+                            modifiers,
                             pos: position,
                             pressed: matches!(event.kind, PointerEventKind::Press { .. }),
                         }
@@ -53,10 +86,16 @@ impl PointerHandler for WgpuLayerShellState {
                 } => egui::Event::MouseWheel {
                     unit: egui::MouseWheelUnit::Point,
                     delta: Vec2::new(-horizontal.absolute as f32, -vertical.absolute as f32),
-                    modifiers: self.egui_state.modifiers(),
+                    modifiers,
                 },
             };
-            self.egui_state.push_event(egui_event);
+            surface.egui_state.push_event(egui_event);
+        }
+        if let (Some(seat), Some(serial)) = (&seat_id, pressed) {
+            self.set_input_serial(seat, serial);
+        }
+        if self.pointer_output != pointer_output {
+            self.replace_misplaced();
         }
     }
 }
@@ -0,0 +1,203 @@
+use sctk::shell::wlr_layer::{Anchor, KeyboardInteractivity, Layer};
+use tracing::info;
+
+use super::{
+    AutoSize, HideStrategy, LayerShellOptions, OutputSelector, SurfaceId, Transition,
+    WgpuLayerShellState,
+};
+
+/// Changes to the [`LayerShellOptions`] of a surface, `None` fields are left as they are.
+#[derive(Default, Clone, Debug)]
+pub struct LayerShellOptionsPatch {
+    pub layer: Option<Layer>,
+    /// Re-creates the layer surface, the namespace is fixed once it exists.
+    pub namespace: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub anchor: Option<Anchor>,
+    pub keyboard_interactivity: Option<KeyboardInteractivity>,
+    pub margin: Option<(i32, i32, i32, i32)>,
+    pub exclusive_zone: Option<i32>,
+    /// Re-creates the layer surface if it resolves to another output.
+    pub output: Option<OutputSelector>,
+    pub auto_size: Option<Option<AutoSize>>,
+    pub hide_strategy: Option<HideStrategy>,
+    pub open_transition: Option<Option<Transition>>,
+    pub close_transition: Option<Option<Transition>>,
+}
+
+impl LayerShellOptionsPatch {
+    /// Applies the patch, returns whether the layer surface has to be re-created.
+    fn merge_into(self, options: &mut LayerShellOptions) -> bool {
+        let recreate = self
+            .namespace
+            .as_ref()
+            .is_some_and(|namespace| *namespace != options.namespace);
+        if let Some(namespace) = self.namespace {
+            options.namespace = namespace;
+        }
+        if let Some(width) = self.width {
+            options.width = width;
+        }
+        if let Some(height) = self.height {
+            options.height = height;
+        }
+        if let Some(margin) = self.margin {
+            options.margin = margin;
+        }
+        if let Some(auto_size) = self.auto_size {
+            options.auto_size = auto_size;
+        }
+        if let Some(hide_strategy) = self.hide_strategy {
+            options.hide_strategy = hide_strategy;
+        }
+        if let Some(open_transition) = self.open_transition {
+            options.open_transition = open_transition;
+        }
+        if let Some(close_transition) = self.close_transition {
+            options.close_transition = close_transition;
+        }
+        options.layer = self.layer.or(options.layer);
+        options.anchor = self.anchor.or(options.anchor);
+        options.keyboard_interactivity = self
+            .keyboard_interactivity
+            .or(options.keyboard_interactivity);
+        options.exclusive_zone = self.exclusive_zone.or(options.exclusive_zone);
+        options.output = self.output.or(options.output.take());
+        recreate
+    }
+}
+
+impl WgpuLayerShellState {
+    /// Applies `patch` to the surface in a single commit.
+    ///
+    /// Namespace and output changes re-create the layer surface, keeping the egui state and app.
+    pub fn reconfigure(&mut self, id: SurfaceId, patch: LayerShellOptionsPatch) {
+        let target = patch
+            .output
+            .as_ref()
+            .map(|selector| self.resolve_output(selector));
+        let Some(surface) = self.surfaces.get_mut(&id) else {
+            return;
+        };
+        let resized = patch.width.is_some() || patch.height.is_some();
+        let layer = patch.layer;
+        let mut recreate = patch.merge_into(&mut surface.layer_opts);
+        recreate |= target.is_some_and(|target| target != surface.output);
+        if resized {
+            surface.auto_size_requested = None;
+        }
+        if recreate {
+            info!("reconfiguring {:?} needs a new layer surface", id);
+            surface.recreate = true;
+            return;
+        }
+
+        let swapped_out = surface.is_hidden() && surface.hide_strategy() == HideStrategy::LayerSwap;
+        if let Some(layer) = layer {
+            // Hiding by layer swap keeps the surface on the background layer.
+            if !swapped_out {
+                surface.current_layer = layer;
+                if let Some(layer_surface) = surface.role.layer() {
+                    layer_surface.set_layer(layer);
+                }
+            }
+        }
+        surface.set_layer_opts();
+        surface.egui_state.context().request_repaint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> LayerShellOptions {
+        LayerShellOptions {
+            namespace: "popup".into(),
+            width: 200,
+            height: 100,
+            layer: Some(Layer::Top),
+            anchor: Some(Anchor::TOP),
+            exclusive_zone: Some(0),
+            output: Some(OutputSelector::Primary),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merges_set_fields_only() {
+        let cases = [
+            (LayerShellOptionsPatch::default(), false, options()),
+            (
+                LayerShellOptionsPatch {
+                    width: Some(300),
+                    layer: Some(Layer::Overlay),
+                    ..Default::default()
+                },
+                false,
+                LayerShellOptions {
+                    width: 300,
+                    layer: Some(Layer::Overlay),
+                    ..options()
+                },
+            ),
+            (
+                LayerShellOptionsPatch {
+                    margin: Some((1, 2, 3, 4)),
+                    exclusive_zone: Some(-1),
+                    ..Default::default()
+                },
+                false,
+                LayerShellOptions {
+                    margin: (1, 2, 3, 4),
+                    exclusive_zone: Some(-1),
+                    ..options()
+                },
+            ),
+            (
+                LayerShellOptionsPatch {
+                    namespace: Some("popup".into()),
+                    ..Default::default()
+                },
+                false,
+                options(),
+            ),
+            (
+                LayerShellOptionsPatch {
+                    namespace: Some("panel".into()),
+                    ..Default::default()
+                },
+                true,
+                LayerShellOptions {
+                    namespace: "panel".into(),
+                    ..options()
+                },
+            ),
+            (
+                LayerShellOptionsPatch {
+                    output: Some(OutputSelector::UnderPointer),
+                    close_transition: Some(None),
+                    ..Default::default()
+                },
+                false,
+                LayerShellOptions {
+                    output: Some(OutputSelector::UnderPointer),
+                    ..options()
+                },
+            ),
+        ];
+        for (patch, recreate, merged) in cases {
+            let mut options = options();
+            let description = format!("{:?}", patch);
+            assert_eq!(patch.merge_into(&mut options), recreate, "{}", description);
+            // LayerShellOptions is not PartialEq.
+            assert_eq!(
+                format!("{:?}", options),
+                format!("{:?}", merged),
+                "{}",
+                description
+            );
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use exponential_backoff::Backoff;
+use sctk::reexports::calloop::timer::{TimeoutAction, Timer};
+use tracing::{info, warn};
+
+use super::WgpuLayerShellState;
+use crate::application::WPEvent;
+
+const RECONNECT_ATTEMPTS: u32 = 12;
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(5);
+
+impl WgpuLayerShellState {
+    /// The compositor went away, e.g. because it restarted.
+    pub(crate) fn connection_lost(&self) -> bool {
+        self.connection.flush().is_err()
+    }
+
+    /// Connects to the compositor again on a timer, retrying with exponential backoff until
+    /// it is back. Surfaces are not drawn meanwhile, [`Self::reconnect_error`] is set when
+    /// giving up.
+    ///
+    /// Every surface is re-created on the new connection, keeping its egui state and app.
+    /// The wgpu device does not depend on the connection and is kept, so are the textures
+    /// egui uploaded to it. [`WPEvent::Reconnected`] is sent once done.
+    pub(crate) fn reconnect(&mut self) -> anyhow::Result<()> {
+        // The dead connection would fail every dispatch.
+        self.loop_handle.remove(self.wayland_token);
+        self.reconnecting = true;
+        for surface in self.surfaces.values_mut() {
+            surface.has_frame_callback = false;
+        }
+
+        let mut backoff =
+            Backoff::new(RECONNECT_ATTEMPTS, RECONNECT_MIN_DELAY, RECONNECT_MAX_DELAY).into_iter();
+        self.loop_handle
+            .insert_source(Timer::immediate(), move |_, _, state| {
+                match Self::new(
+                    state.loop_handle.clone(),
+                    state.ev.clone(),
+                    state.ev_rx.clone(),
+                ) {
+                    Ok(new) => {
+                        state.replace_connection(new);
+                        info!("reconnected to the compositor");
+                        let _ = state.ev.send(WPEvent::Reconnected);
+                        TimeoutAction::Drop
+                    }
+                    Err(err) => match backoff.next().flatten() {
+                        Some(delay) => {
+                            warn!("reconnecting failed, retrying in {:?}: {}", delay, err);
+                            TimeoutAction::ToDuration(delay)
+                        }
+                        None => {
+                            state.reconnect_error = Some(err);
+                            TimeoutAction::Drop
+                        }
+                    },
+                }
+            })
+            .map_err(|e| anyhow::Error::new(e.error).context("could not reconnect"))?;
+        Ok(())
+    }
+
+    /// Moves the device, the surfaces, the event subscribers and the settings made by the
+    /// apps over to `state`, connected to the new compositor. Everything else belongs to the
+    /// old connection and is dropped with it.
+    fn replace_connection(&mut self, mut state: Self) {
+        state.wgpu_state = self.wgpu_state.take();
+        state.surfaces = std::mem::take(&mut self.surfaces);
+        state.subscribers = std::mem::take(&mut self.subscribers);
+        // The new state starts from the defaults.
+        state.selection_delivery = self.selection_delivery;
+        state.mime_priority = std::mem::take(&mut self.mime_priority);
+        state.clipboard_history = self.clipboard_history.take();
+        state.ime_purpose = self.ime_purpose;
+        state.ime_allowed = self.ime_allowed;
+        // Input methods are created again as the seats show up.
+        state.input_method_enabled = self.input_method_enabled;
+        for surface in state.surfaces.values_mut() {
+            // Proxies of the old connection are dead, only the options carry over.
+            surface.output = None;
+            surface.entered_outputs.clear();
+            surface.has_frame_callback = false;
+            surface.recreate = true;
+        }
+        // Drops the globals of the old connection.
+        *self = state;
+    }
+}
@@ -0,0 +1,179 @@
+//! Input state kept for every seat, a second seat types and points independently of the first.
+
+use std::sync::Arc;
+
+use sctk::reexports::protocols::ext::data_control::v1::client::ext_data_control_device_v1::ExtDataControlDeviceV1;
+use sctk::reexports::protocols::wp::primary_selection::zv1::client::zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1;
+use sctk::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_v3::ZwpTextInputV3;
+use sctk::reexports::protocols_wlr::data_control::v1::client::zwlr_data_control_device_v1::ZwlrDataControlDeviceV1;
+use wayland_backend::client::ObjectId;
+use wayland_client::{
+    protocol::{
+        wl_data_device::WlDataDevice, wl_keyboard::WlKeyboard, wl_pointer::WlPointer,
+        wl_seat::WlSeat,
+    },
+    Proxy,
+};
+
+use super::{InputMethod, SurfaceId, VirtualKeyboard, WgpuLayerShellState};
+use crate::text_input::TextInputClientState;
+
+/// Devices of a seat and what they are focused on.
+pub struct PerSeat {
+    pub seat: WlSeat,
+    pub pointer: Option<WlPointer>,
+    pub keyboard: Option<WlKeyboard>,
+    /// The surface this seat types into.
+    pub keyboard_focus: Option<SurfaceId>,
+    pub modifiers: egui::Modifiers,
+    pub text_input: Option<Arc<ZwpTextInputV3>>,
+    /// Whether the text input entered one of the surfaces.
+    pub text_input_entered: bool,
+    /// The IME state sent to the text input, `None` while disabled.
+    pub text_input_state: Option<TextInputClientState>,
+    /// Serial of the last key press or pointer button, to set the clipboard with.
+    pub serial: u32,
+    /// Only one data control device is used, ext-data-control is preferred.
+    pub ext_data_device: Option<ExtDataControlDeviceV1>,
+    pub data_device: Option<ZwlrDataControlDeviceV1>,
+    pub wl_data_device: Option<WlDataDevice>,
+    pub primary_selection_device: Option<ZwpPrimarySelectionDeviceV1>,
+    /// Present while acting as the input method, see
+    /// [`WgpuLayerShellState::set_input_method`].
+    pub input_method: Option<InputMethod>,
+    /// Created on first use, see [`WgpuLayerShellState::type_text`].
+    pub virtual_keyboard: Option<VirtualKeyboard>,
+}
+
+impl PerSeat {
+    pub fn new(seat: WlSeat) -> Self {
+        Self {
+            seat,
+            pointer: None,
+            keyboard: None,
+            keyboard_focus: None,
+            modifiers: Default::default(),
+            text_input: None,
+            text_input_entered: false,
+            text_input_state: None,
+            serial: 0,
+            ext_data_device: None,
+            data_device: None,
+            wl_data_device: None,
+            primary_selection_device: None,
+            input_method: None,
+            virtual_keyboard: None,
+        }
+    }
+
+    pub(crate) fn has_data_control_device(&self) -> bool {
+        self.ext_data_device.is_some() || self.data_device.is_some()
+    }
+
+    /// Releases the devices of a seat that is gone.
+    pub(crate) fn release(self) {
+        if let Some(pointer) = self.pointer {
+            pointer.release();
+        }
+        if let Some(keyboard) = self.keyboard {
+            keyboard.release();
+        }
+        if let Some(text_input) = self.text_input {
+            text_input.destroy();
+        }
+        if let Some(device) = self.ext_data_device {
+            device.destroy();
+        }
+        if let Some(device) = self.data_device {
+            device.destroy();
+        }
+        if let Some(device) = self.wl_data_device {
+            // Destructor since version 2.
+            if device.version() >= 2 {
+                device.release();
+            }
+        }
+        if let Some(device) = self.primary_selection_device {
+            device.destroy();
+        }
+        if let Some(input_method) = self.input_method {
+            input_method.destroy();
+        }
+        if let Some(virtual_keyboard) = self.virtual_keyboard {
+            virtual_keyboard.destroy();
+        }
+    }
+}
+
+impl WgpuLayerShellState {
+    /// Name of the seat, carried by the events of its devices.
+    pub fn seat_name(&self, seat: &ObjectId) -> Option<String> {
+        let per_seat = self.seat_map.get(seat)?;
+        self.seat_state.info(&per_seat.seat)?.name
+    }
+
+    pub(crate) fn seat_of_keyboard(&self, keyboard: &WlKeyboard) -> Option<ObjectId> {
+        self.seat_map
+            .iter()
+            .find(|(_, s)| s.keyboard.as_ref() == Some(keyboard))
+            .map(|(id, _)| id.clone())
+    }
+
+    pub(crate) fn seat_of_pointer(&self, pointer: &WlPointer) -> Option<ObjectId> {
+        self.seat_map
+            .iter()
+            .find(|(_, s)| s.pointer.as_ref() == Some(pointer))
+            .map(|(id, _)| id.clone())
+    }
+
+    pub(crate) fn seat_of_text_input(&self, text_input: &ZwpTextInputV3) -> Option<ObjectId> {
+        self.seat_map
+            .iter()
+            .find(|(_, s)| s.text_input.as_deref() == Some(text_input))
+            .map(|(id, _)| id.clone())
+    }
+
+    /// Records the serial of an input event, making the seat the one selections are set on.
+    pub(crate) fn set_input_serial(&mut self, seat: &ObjectId, serial: u32) {
+        if let Some(per_seat) = self.seat_map.get_mut(seat) {
+            per_seat.serial = serial;
+            self.input_seat = Some(seat.clone());
+        }
+    }
+
+    /// Seats whose keyboard is focused on the surface.
+    pub(crate) fn seats_focused_on(&self, id: SurfaceId) -> Vec<ObjectId> {
+        self.seat_map
+            .iter()
+            .filter(|(_, s)| s.keyboard_focus == Some(id))
+            .map(|(seat, _)| seat.clone())
+            .collect()
+    }
+
+    /// Seats with the IME enabled.
+    pub(crate) fn seats_with_ime(&self) -> Vec<ObjectId> {
+        self.seat_map
+            .iter()
+            .filter(|(_, s)| s.text_input_state.is_some())
+            .map(|(seat, _)| seat.clone())
+            .collect()
+    }
+
+    /// Input of the surface the seat's keyboard is focused on.
+    pub(crate) fn focused_input(&mut self, seat: &ObjectId) -> Option<&mut egui::RawInput> {
+        let id = self.seat_map.get(seat)?.keyboard_focus?;
+        self.surfaces.get_mut(&id).map(|s| s.egui_state.input())
+    }
+
+    /// Moves the seat's keyboard focus, keeping `keyboard_focus` on a focused surface.
+    pub(crate) fn set_seat_focus(&mut self, seat: &ObjectId, focus: Option<SurfaceId>) {
+        if let Some(per_seat) = self.seat_map.get_mut(seat) {
+            per_seat.keyboard_focus = focus;
+        }
+        self.keyboard_focus = focus.or_else(|| {
+            self.seat_map
+                .values()
+                .find_map(|per_seat| per_seat.keyboard_focus)
+        });
+    }
+}
@@ -0,0 +1,435 @@
+use std::{
+    cell::Cell,
+    io::{self, pipe, ErrorKind, PipeReader, Read},
+    os::fd::{AsFd, AsRawFd, BorrowedFd},
+    rc::Rc,
+    sync::Mutex,
+    time::Duration,
+};
+
+use sctk::reexports::calloop::{
+    generic::Generic, timer::TimeoutAction, timer::Timer, Interest, Mode, PostAction,
+    RegistrationToken,
+};
+use sctk::reexports::protocols::ext::data_control::v1::client::ext_data_control_offer_v1::ExtDataControlOfferV1;
+use sctk::reexports::protocols::wp::primary_selection::zv1::client::zwp_primary_selection_offer_v1::ZwpPrimarySelectionOfferV1;
+use sctk::reexports::protocols_wlr::data_control::v1::client::zwlr_data_control_offer_v1::ZwlrDataControlOfferV1;
+use tracing::warn;
+use wayland_backend::client::ObjectId;
+use wayland_client::{protocol::wl_data_offer::WlDataOffer, Proxy};
+
+use super::cliphandler::TEXT;
+use super::WgpuLayerShellState;
+use crate::application::WPEvent;
+
+/// Where a selection came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelectionSource {
+    /// Copied with ctrl+c and the like.
+    Clipboard,
+    /// Selected text, pasted with the middle mouse button.
+    Primary,
+}
+
+/// How new selections reach the apps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionDelivery {
+    /// Read on the event loop and sent as [`WPEvent::Selection`]. Selections larger than
+    /// `max_size` bytes or taking longer than `timeout` to arrive are dropped.
+    Read { max_size: usize, timeout: Duration },
+    /// Sent as [`WPEvent::Fd`] right away, for streaming large payloads.
+    Fd,
+    /// Only announced with [`WPEvent::SelectionOffered`], apps read the MIME type they
+    /// want with [`WgpuLayerShellState::read_selection`].
+    OnRequest,
+}
+
+const DEFAULT_MAX_SIZE: usize = 1 << 20;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl Default for SelectionDelivery {
+    fn default() -> Self {
+        SelectionDelivery::Read {
+            max_size: DEFAULT_MAX_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl SelectionDelivery {
+    fn read_limits(self) -> (usize, Duration) {
+        match self {
+            SelectionDelivery::Read { max_size, timeout } => (max_size, timeout),
+            SelectionDelivery::Fd | SelectionDelivery::OnRequest => {
+                (DEFAULT_MAX_SIZE, DEFAULT_TIMEOUT)
+            }
+        }
+    }
+}
+
+/// MIME types holding plain text, best first.
+pub(crate) const TEXT_MIMES: [&str; 5] = [TEXT, "text/plain", "UTF8_STRING", "STRING", "TEXT"];
+
+/// Whether `mime` holds text that can be decoded as UTF-8.
+pub(crate) fn is_text_mime(mime: &str) -> bool {
+    if TEXT_MIMES.contains(&mime) {
+        return true;
+    }
+    let mut parts = mime.split(';');
+    let essence = parts.next().unwrap_or_default().trim();
+    // Text without a charset is taken to be UTF-8.
+    let utf8 = parts
+        .filter_map(|param| param.split_once('='))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .all(|(_, charset)| {
+            let charset = charset.trim().trim_matches('"');
+            charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("us-ascii")
+        });
+    essence.starts_with("text/") && utf8
+}
+
+/// Picks the MIME type to receive an offer of `mimes` in.
+///
+/// The first one in `priority` that is offered wins, otherwise text is preferred.
+pub(crate) fn pick_mime(mimes: &[String], priority: &[String]) -> Option<String> {
+    let offered = |mime: &str| mimes.iter().any(|m| m == mime);
+    priority
+        .iter()
+        .map(String::as_str)
+        .chain(TEXT_MIMES)
+        .find(|mime| offered(mime))
+        .map(str::to_owned)
+        .or_else(|| mimes.first().cloned())
+}
+
+/// MIME types of an offer, collected from its `offer` events.
+#[derive(Default)]
+pub(crate) struct OfferMimes(Mutex<Vec<String>>);
+
+impl OfferMimes {
+    pub(crate) fn push(&self, mime: String) {
+        self.0.lock().unwrap().push(mime);
+    }
+
+    fn get(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// The offer of a current selection, kept until it is replaced.
+pub(crate) enum SelectionOffer {
+    Ext(ExtDataControlOfferV1),
+    Wlr(ZwlrDataControlOfferV1),
+    /// Only while a surface has keyboard focus, without data control.
+    Primary(ZwpPrimarySelectionOfferV1),
+    Data(WlDataOffer),
+}
+
+impl SelectionOffer {
+    pub(crate) fn mime_types(&self) -> Vec<String> {
+        let mimes = match self {
+            SelectionOffer::Ext(offer) => offer.data::<OfferMimes>(),
+            SelectionOffer::Wlr(offer) => offer.data::<OfferMimes>(),
+            SelectionOffer::Primary(offer) => offer.data::<OfferMimes>(),
+            SelectionOffer::Data(offer) => offer.data::<OfferMimes>(),
+        };
+        mimes.map(OfferMimes::get).unwrap_or_default()
+    }
+
+    pub(crate) fn receive(&self, mime: String, fd: BorrowedFd) {
+        match self {
+            SelectionOffer::Ext(offer) => offer.receive(mime, fd),
+            SelectionOffer::Wlr(offer) => offer.receive(mime, fd),
+            SelectionOffer::Primary(offer) => offer.receive(mime, fd),
+            SelectionOffer::Data(offer) => offer.receive(mime, fd),
+        }
+    }
+
+    fn destroy(&self) {
+        match self {
+            SelectionOffer::Ext(offer) => offer.destroy(),
+            SelectionOffer::Wlr(offer) => offer.destroy(),
+            SelectionOffer::Primary(offer) => offer.destroy(),
+            SelectionOffer::Data(offer) => offer.destroy(),
+        }
+    }
+}
+
+/// Creates a pipe and has `receive` ask the compositor to write the offer into it.
+pub(crate) fn receive_into_pipe(
+    mime: String,
+    receive: impl FnOnce(String, BorrowedFd),
+) -> Option<PipeReader> {
+    let (reader, writer) = match pipe() {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            warn!("could not create a pipe for the selection: {}", e);
+            return None;
+        }
+    };
+    receive(mime, writer.as_fd());
+    Some(reader)
+}
+
+pub(crate) fn set_nonblocking(pipe: &impl AsRawFd) -> io::Result<()> {
+    let fd = pipe.as_raw_fd();
+    // SAFETY: fd is a valid pipe for the lifetime of `pipe`.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl WgpuLayerShellState {
+    pub fn set_selection_delivery(&mut self, delivery: SelectionDelivery) {
+        self.selection_delivery = delivery;
+    }
+
+    /// MIME types to read new selections in, most wanted first.
+    ///
+    /// Text is read when none of them is offered.
+    pub fn set_mime_priority(&mut self, priority: Vec<String>) {
+        self.mime_priority = priority;
+    }
+
+    /// MIME types the current selection is offered in, empty without a selection.
+    pub fn selection_mime_types(&self, source: SelectionSource) -> Vec<String> {
+        self.selection_offers
+            .get(&source)
+            .map(SelectionOffer::mime_types)
+            .unwrap_or_default()
+    }
+
+    /// Replaces the current selection with the one made on `seat`, `None` when it was cleared.
+    ///
+    /// Announces the new one and reads it in the preferred MIME type.
+    pub(crate) fn set_selection_offer(
+        &mut self,
+        source: SelectionSource,
+        seat: &ObjectId,
+        offer: Option<SelectionOffer>,
+    ) {
+        self.selection_seats.insert(source, seat.clone());
+        let replaced = match offer {
+            Some(offer) => self.selection_offers.insert(source, offer),
+            None => self.selection_offers.remove(&source),
+        };
+        if let Some(replaced) = replaced {
+            replaced.destroy();
+        }
+        let mimes = self.selection_mime_types(source);
+        if mimes.is_empty() {
+            return;
+        }
+        self.emit(WPEvent::SelectionOffered {
+            source,
+            seat: self.seat_name(seat),
+            mimes: mimes.clone(),
+        });
+        // Our own selection coming back, the apps know what they set.
+        if self.owns_selection(source) {
+            return;
+        }
+        let Some(mime) = pick_mime(&mimes, &self.mime_priority) else {
+            return;
+        };
+        let delivery = self.selection_delivery;
+        // Nobody would receive it.
+        let deliver = delivery != SelectionDelivery::OnRequest && self.has_listeners();
+        if deliver {
+            self.read_selection(source, mime.clone());
+        }
+        // Selections read for the apps are recorded as they arrive, others need a read of
+        // their own.
+        let record = source == SelectionSource::Clipboard && self.clipboard_history.is_some();
+        if record && !(deliver && matches!(delivery, SelectionDelivery::Read { .. })) {
+            self.read_offer(source, mime.clone(), move |state, data| {
+                state.record_history(mimes, mime, data);
+            });
+        }
+    }
+
+    /// Reads the current selection as `mime` and hands it to the apps, according to the
+    /// [`SelectionDelivery`].
+    pub fn read_selection(&mut self, source: SelectionSource, mime: String) {
+        if self.selection_delivery == SelectionDelivery::Fd {
+            if let Some(reader) = self.receive_offer(source, mime) {
+                self.emit(WPEvent::Fd(reader));
+            }
+            return;
+        }
+        let seat = self
+            .selection_seats
+            .get(&source)
+            .and_then(|seat| self.seat_name(seat));
+        let mimes = self.selection_mime_types(source);
+        self.read_offer(source, mime.clone(), move |state, data| {
+            if source == SelectionSource::Clipboard && state.clipboard_history.is_some() {
+                state.record_history(mimes, mime.clone(), data.clone());
+            }
+            state.emit(WPEvent::Selection {
+                source,
+                mime,
+                seat,
+                data,
+            });
+        });
+    }
+
+    /// Reads the current selection as `mime` on the event loop, see [`Self::read_pipe`].
+    pub(crate) fn read_offer(
+        &mut self,
+        source: SelectionSource,
+        mime: String,
+        done: impl FnOnce(&mut Self, Vec<u8>) + 'static,
+    ) {
+        if let Some(reader) = self.receive_offer(source, mime) {
+            self.read_pipe(reader, source, done);
+        }
+    }
+
+    fn receive_offer(&self, source: SelectionSource, mime: String) -> Option<PipeReader> {
+        let Some(offer) = self.selection_offers.get(&source) else {
+            warn!("there is no {:?} selection to read", source);
+            return None;
+        };
+        receive_into_pipe(mime, |mime, fd| offer.receive(mime, fd))
+    }
+
+    /// Reads `reader` to the end on the event loop, then calls `done` with the data.
+    ///
+    /// Gives up past the size limit and timeout of [`SelectionDelivery::Read`], the
+    /// defaults apply otherwise.
+    pub(crate) fn read_pipe(
+        &self,
+        reader: PipeReader,
+        source: SelectionSource,
+        done: impl FnOnce(&mut Self, Vec<u8>) + 'static,
+    ) {
+        let (max_size, timeout) = self.selection_delivery.read_limits();
+        if let Err(e) = set_nonblocking(&reader) {
+            warn!("could not read the selection: {}", e);
+            return;
+        }
+
+        let reader_token: Rc<Cell<Option<RegistrationToken>>> = Rc::default();
+        let timer_token = self
+            .loop_handle
+            .insert_source(Timer::from_duration(timeout), {
+                let reader_token = Rc::clone(&reader_token);
+                move |_, _, state| {
+                    warn!("reading the {:?} selection timed out", source);
+                    if let Some(token) = reader_token.take() {
+                        state.loop_handle.remove(token);
+                    }
+                    TimeoutAction::Drop
+                }
+            });
+        let Ok(timer_token) = timer_token else {
+            warn!("could not time out reading the selection");
+            return;
+        };
+
+        let mut done = Some(done);
+        let mut data = Vec::new();
+        let mut chunk = [0; 4096];
+        let token = self.loop_handle.insert_source(
+            Generic::new(reader, Interest::READ, Mode::Level),
+            move |_, reader, state| loop {
+                match (&**reader).read(&mut chunk) {
+                    Ok(0) => {
+                        state.loop_handle.remove(timer_token);
+                        if let Some(done) = done.take() {
+                            done(state, std::mem::take(&mut data));
+                        }
+                        return Ok(PostAction::Remove);
+                    }
+                    Ok(read) if data.len() + read > max_size => {
+                        warn!("{:?} selection exceeds {} bytes", source, max_size);
+                        state.loop_handle.remove(timer_token);
+                        return Ok(PostAction::Remove);
+                    }
+                    Ok(read) => data.extend_from_slice(&chunk[..read]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(PostAction::Continue),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        warn!("could not read the {:?} selection: {}", source, e);
+                        state.loop_handle.remove(timer_token);
+                        return Ok(PostAction::Remove);
+                    }
+                }
+            },
+        );
+        match token {
+            Ok(token) => reader_token.set(Some(token)),
+            Err(e) => {
+                warn!("could not read the selection: {}", e.error);
+                self.loop_handle.remove(timer_token);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mimes(mimes: &[&str]) -> Vec<String> {
+        mimes.iter().map(|mime| mime.to_string()).collect()
+    }
+
+    #[test]
+    fn text_mimes() {
+        let cases = [
+            (TEXT, true),
+            ("text/plain", true),
+            ("UTF8_STRING", true),
+            ("STRING", true),
+            ("TEXT", true),
+            ("text/html", true),
+            ("text/plain;charset=UTF-8", true),
+            ("text/plain; charset=\"utf-8\"", true),
+            ("text/plain;charset=us-ascii", true),
+            ("text/plain;charset=utf-16", false),
+            ("text/plain;charset=iso-8859-1", false),
+            ("image/png", false),
+            ("application/json", false),
+            ("x-kde-passwordManagerHint", false),
+            ("", false),
+        ];
+        for (mime, text) in cases {
+            assert_eq!(is_text_mime(mime), text, "{}", mime);
+        }
+    }
+
+    #[test]
+    fn text_mimes_best_first() {
+        assert_eq!(TEXT_MIMES[0], TEXT);
+        assert!(TEXT_MIMES.iter().all(|mime| is_text_mime(mime)));
+    }
+
+    #[test]
+    fn picks_mime() {
+        const PNG: &str = "image/png";
+        const HTML: &str = "text/html";
+        const UPPER: &str = "text/plain;charset=UTF-8";
+        let cases: [(&[&str], &[&str], Option<&str>); 8] = [
+            // offered, priority, picked
+            (&[PNG, TEXT], &[PNG], Some(PNG)),
+            (&[PNG, TEXT], &[HTML, PNG], Some(PNG)),
+            (&[PNG, TEXT], &[HTML], Some(TEXT)),
+            (&["STRING", "text/plain", TEXT], &[], Some(TEXT)),
+            (&["STRING", "UTF8_STRING"], &[], Some("UTF8_STRING")),
+            // Nothing matches, the first one offered is taken.
+            (&[UPPER], &[], Some(UPPER)),
+            (&[PNG, "image/jpeg"], &[HTML], Some(PNG)),
+            (&[], &[PNG], None),
+        ];
+        for (offered, priority, picked) in cases {
+            let (offered, priority) = (mimes(offered), mimes(priority));
+            let picked = picked.map(str::to_owned);
+            assert_eq!(pick_mime(&offered, &priority), picked, "{:?}", offered);
+        }
+    }
+}
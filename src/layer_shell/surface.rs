@@ -0,0 +1,388 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Instant,
+};
+
+use sctk::reexports::protocols::wp::{
+    fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1,
+    viewporter::client::wp_viewport::WpViewport,
+};
+use sctk::{
+    reexports::calloop::RegistrationToken,
+    shell::{
+        wlr_layer::{Layer, LayerSurface},
+        WaylandSurface,
+    },
+};
+use wayland_client::protocol::{wl_output::WlOutput, wl_surface::WlSurface};
+use wgpu::Device;
+
+use crate::{
+    application::{SurfaceEvent, WPEvent},
+    egui_state,
+    layer_shell::{
+        input_method::InputPopup, input_region::RegionRect, pixels_per_point,
+        transition::ActiveTransition, HideStrategy, LayerShellOptions,
+    },
+    wgpu_state::WgpuSurface,
+    App,
+};
+
+/// Identifies one layer surface of a [`crate::application::WgpuLayerShellApp`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SurfaceId(u64);
+
+impl SurfaceId {
+    /// Allocates a process-wide unique id.
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// The role of the wl_surface of a [`PopupSurface`].
+pub enum SurfaceRole {
+    Layer(LayerSurface),
+    /// Placed by the compositor at the text cursor, see
+    /// [`super::WgpuLayerShellState::set_input_method`].
+    InputPopup(InputPopup),
+}
+
+impl SurfaceRole {
+    pub fn layer(&self) -> Option<&LayerSurface> {
+        match self {
+            SurfaceRole::Layer(layer) => Some(layer),
+            SurfaceRole::InputPopup(_) => None,
+        }
+    }
+
+    pub fn input_popup(&self) -> Option<&InputPopup> {
+        match self {
+            SurfaceRole::Layer(_) => None,
+            SurfaceRole::InputPopup(popup) => Some(popup),
+        }
+    }
+
+    pub(crate) fn input_popup_mut(&mut self) -> Option<&mut InputPopup> {
+        match self {
+            SurfaceRole::Layer(_) => None,
+            SurfaceRole::InputPopup(popup) => Some(popup),
+        }
+    }
+}
+
+impl WaylandSurface for SurfaceRole {
+    fn wl_surface(&self) -> &WlSurface {
+        match self {
+            SurfaceRole::Layer(layer) => layer.wl_surface(),
+            SurfaceRole::InputPopup(popup) => popup.wl_surface(),
+        }
+    }
+}
+
+/// A layer surface or input popup together with its swapchain, egui context and [`App`].
+pub struct PopupSurface {
+    id: SurfaceId,
+    // Dropped before `role`, the swapchain must not outlive the wl_surface.
+    pub(crate) wgpu_surface: WgpuSurface,
+    pub egui_state: egui_state::State,
+    pub(crate) role: SurfaceRole,
+    pub current_layer: Layer,
+    pub layer_opts: LayerShellOptions,
+    pub(crate) passthrough: bool,
+    pub(crate) auto_passthrough: bool,
+    /// Input region last set in auto passthrough mode.
+    pub(crate) input_region: Option<Vec<RegionRect>>,
+    pub(crate) hidden: bool,
+    pub(crate) transition: Option<ActiveTransition>,
+
+    pub(crate) has_frame_callback: bool,
+    pub(crate) is_configured: bool,
+    /// Size in surface-local coordinates from the last configure.
+    pub(crate) logical_size: (u32, u32),
+    /// Last size requested by [`super::AutoSize`].
+    pub(crate) auto_size_requested: Option<(u32, u32)>,
+    /// Preferred fractional scale, or the integer buffer scale without a viewport.
+    pub(crate) scale_factor: f64,
+    pub(crate) viewport: Option<WpViewport>,
+    pub(crate) fractional_scale: Option<WpFractionalScaleV1>,
+    /// The output the surface was created on, if one was selected.
+    pub(crate) output: Option<WlOutput>,
+    /// Outputs the surface is currently shown on.
+    pub(crate) entered_outputs: Vec<WlOutput>,
+    /// The layer surface was closed by the compositor or has to move to another output.
+    /// It is re-created by the event loop.
+    pub(crate) recreate: bool,
+    pub(crate) draw_request: Arc<RwLock<Option<Instant>>>,
+    ev: flume::Sender<WPEvent>,
+
+    /// Taken out while the app is being synced or updated.
+    pub(crate) app: Option<Box<dyn App>>,
+    pub(crate) msg_token: Option<RegistrationToken>,
+}
+
+impl PopupSurface {
+    pub(crate) fn new(
+        id: SurfaceId,
+        role: SurfaceRole,
+        wgpu_surface: WgpuSurface,
+        egui_state: egui_state::State,
+        layer_opts: LayerShellOptions,
+        ev: flume::Sender<WPEvent>,
+    ) -> Self {
+        let draw_request = Arc::new(RwLock::new(None));
+
+        egui_state.context().set_request_repaint_callback({
+            let draw_request = Arc::clone(&draw_request);
+            move |info| {
+                let mut draw_request = draw_request.write().unwrap();
+                *draw_request = Some(Instant::now() + info.delay);
+            }
+        });
+
+        let current_layer = layer_opts.layer.unwrap_or(Layer::Top);
+        let hidden = current_layer == Layer::Background;
+        let transition = layer_opts
+            .open_transition
+            .filter(|_| !hidden)
+            .map(|open| ActiveTransition::new(open, false, None));
+        Self {
+            id,
+            wgpu_surface,
+            egui_state,
+            current_layer,
+            role,
+            layer_opts,
+            passthrough: false,
+            auto_passthrough: false,
+            input_region: None,
+            hidden,
+            transition,
+            has_frame_callback: false,
+            is_configured: false,
+            logical_size: (0, 0),
+            auto_size_requested: None,
+            scale_factor: 1.,
+            viewport: None,
+            fractional_scale: None,
+            output: None,
+            entered_outputs: Vec::new(),
+            recreate: false,
+            draw_request,
+            ev,
+            app: None,
+            msg_token: None,
+        }
+    }
+
+    pub fn id(&self) -> SurfaceId {
+        self.id
+    }
+
+    /// `None` for an input popup.
+    pub fn layer_surface(&self) -> Option<&LayerSurface> {
+        self.role.layer()
+    }
+
+    pub fn role(&self) -> &SurfaceRole {
+        &self.role
+    }
+
+    /// Where the text being entered is relative to an input popup, as `(x, y, width, height)`
+    /// in logical pixels.
+    pub fn text_input_rectangle(&self) -> Option<(i32, i32, i32, i32)> {
+        self.role
+            .input_popup()
+            .and_then(|popup| popup.text_input_rectangle)
+    }
+
+    /// Outputs the surface is currently shown on.
+    pub fn outputs(&self) -> &[WlOutput] {
+        &self.entered_outputs
+    }
+
+    pub fn passthrough(&self) -> bool {
+        self.passthrough
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    pub fn pixels_per_point(&self) -> f32 {
+        pixels_per_point(self.egui_state.context(), self.scale_factor as f32)
+    }
+
+    pub(crate) fn emit(&self, event: SurfaceEvent) {
+        // Dropped by the loop when nobody listens.
+        let _ = self.ev.send(WPEvent::Surface(self.id, event));
+    }
+
+    /// Size of the buffer in physical pixels.
+    pub fn physical_size(&self) -> (u32, u32) {
+        let (width, height) = self.logical_size;
+        (
+            (width as f64 * self.scale_factor).round() as u32,
+            (height as f64 * self.scale_factor).round() as u32,
+        )
+    }
+
+    /// Resizes the swapchain to the logical size at the current scale.
+    pub(crate) fn apply_size(&mut self, device: &Device) {
+        let (width, height) = self.logical_size;
+        match &self.viewport {
+            Some(viewport) => viewport.set_destination(width as i32, height as i32),
+            None => self
+                .role
+                .wl_surface()
+                .set_buffer_scale(self.scale_factor as i32),
+        }
+        let (physical_width, physical_height) = self.physical_size();
+        self.wgpu_surface
+            .resize(device, physical_width.max(1), physical_height.max(1));
+        self.egui_state
+            .set_size(width, height, self.scale_factor as f32);
+        self.egui_state.context().request_repaint();
+    }
+
+    pub(crate) fn release_scale_objects(&mut self) {
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+    }
+
+    /// The surface has no buffer attached, as hidden with [`HideStrategy::Unmap`].
+    pub(crate) fn is_unmapped(&self) -> bool {
+        self.hidden && self.hide_strategy() == HideStrategy::Unmap
+    }
+
+    /// Input popups can't change layers, they are always unmapped.
+    pub(crate) fn hide_strategy(&self) -> HideStrategy {
+        match self.role {
+            SurfaceRole::Layer(_) => self.layer_opts.hide_strategy,
+            SurfaceRole::InputPopup(_) => HideStrategy::Unmap,
+        }
+    }
+
+    /// Hides or shows the surface right away according to
+    /// [`LayerShellOptions::hide_strategy`].
+    pub(crate) fn apply_hidden(&mut self, hide: bool) {
+        if hide == self.hidden {
+            return;
+        }
+        match self.hide_strategy() {
+            HideStrategy::LayerSwap => {
+                self.hidden = hide;
+                let layer = if hide {
+                    Layer::Background
+                } else {
+                    self.layer_opts.layer.unwrap_or(Layer::Top)
+                };
+                self.current_layer = layer;
+                if let Some(layer_surface) = self.role.layer() {
+                    layer_surface.set_layer(layer);
+                    layer_surface.commit();
+                }
+            }
+            HideStrategy::Unmap if hide => self.unmap(),
+            HideStrategy::Unmap => self.map(),
+        }
+        self.emit(if hide {
+            SurfaceEvent::Hidden
+        } else {
+            SurfaceEvent::Shown
+        });
+    }
+
+    /// Attaches a null buffer, which resets the layer surface to its unconfigured state.
+    fn unmap(&mut self) {
+        self.hidden = true;
+        self.has_frame_callback = false;
+        self.is_configured = false;
+        let wl_surface = self.role.wl_surface();
+        wl_surface.attach(None, 0, 0);
+        wl_surface.commit();
+    }
+
+    /// Commits without a buffer, drawing resumes with the configure that follows.
+    fn map(&mut self) {
+        self.hidden = false;
+        if self.recreate {
+            // The event loop maps the surface while re-creating it.
+            return;
+        }
+        match &self.role {
+            // Already configured while hidden, e.g. by a size change.
+            SurfaceRole::Layer(_) if self.is_configured => self.start_drawing(),
+            SurfaceRole::Layer(layer) => self.layer_opts.apply(layer),
+            SurfaceRole::InputPopup(_) => {
+                self.is_configured = true;
+                self.start_drawing();
+            }
+        }
+    }
+
+    /// Draws right away, without waiting for a frame callback.
+    pub(crate) fn start_drawing(&mut self) {
+        self.has_frame_callback = true;
+        *self.draw_request.write().unwrap() = Some(Instant::now());
+    }
+
+    pub fn set_layer_opts(&mut self) {
+        if let Some(layer) = self.role.layer() {
+            self.layer_opts.apply(layer);
+        }
+    }
+
+    pub fn set_margin(&mut self, margin: (i32, i32, i32, i32)) {
+        if let Some(layer) = self.role.layer() {
+            layer.set_margin(margin.0, margin.1, margin.2, margin.3);
+            layer.commit();
+        }
+    }
+
+    /// Reserves `zone` logical pixels at the anchored edge, see
+    /// [`LayerShellOptions::exclusive_zone`].
+    pub fn set_exclusive_zone(&mut self, zone: i32) {
+        self.layer_opts.exclusive_zone = Some(zone);
+        if let Some(layer) = self.role.layer() {
+            layer.set_exclusive_zone(zone);
+            layer.commit();
+        }
+    }
+
+    pub(crate) fn should_draw(&mut self) -> bool {
+        if !self.has_frame_callback || self.app.is_none() {
+            return false;
+        }
+
+        if !self.egui_state.input().events.is_empty() {
+            return true;
+        }
+
+        match *self.draw_request.read().unwrap() {
+            Some(time) => time <= Instant::now(),
+            None => false,
+        }
+    }
+
+    pub(crate) fn draw_deadline(&self) -> Option<Instant> {
+        if !self.has_frame_callback {
+            return None;
+        }
+        *self.draw_request.read().unwrap()
+    }
+}
+
+impl Drop for PopupSurface {
+    fn drop(&mut self) {
+        // Must go before the wl_surface they were created for.
+        self.release_scale_objects();
+    }
+}
@@ -0,0 +1,313 @@
+use std::time::{Duration, Instant};
+
+use egui::{emath::TSTransform, Vec2};
+use sctk::shell::wlr_layer::Anchor;
+
+use super::PopupSurface;
+
+/// Size the content starts from with [`TransitionKind::Scale`].
+const SCALE_FROM: f32 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionKind {
+    Fade,
+    /// Slide in from the edge the surface is anchored to, the top edge if that is ambiguous.
+    Slide,
+    /// Grow from the center while fading in.
+    Scale,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    #[default]
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps linear progress in `0..=1` to eased progress, both ends fixed.
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1. - (1. - t).powi(3),
+            Easing::EaseInOut if t < 0.5 => 4. * t * t * t,
+            Easing::EaseInOut => 1. - (-2. * t + 2.).powi(3) / 2.,
+        }
+    }
+}
+
+/// An open or close animation, see [`super::LayerShellOptions::open_transition`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub duration: Duration,
+    pub easing: Easing,
+}
+
+impl Transition {
+    pub fn new(kind: TransitionKind, duration: Duration) -> Self {
+        Self {
+            kind,
+            duration,
+            easing: Easing::default(),
+        }
+    }
+
+    pub fn with_easing(self, easing: Easing) -> Self {
+        Self { easing, ..self }
+    }
+}
+
+/// A transition being played on a surface.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ActiveTransition {
+    transition: Transition,
+    closing: bool,
+    /// Linear visibility the transition starts from, 0 is hidden and 1 fully shown.
+    from: f32,
+    /// Set with the first frame drawn, the surface may wait for a configure before that.
+    start: Option<Instant>,
+}
+
+impl ActiveTransition {
+    /// Starts from where `interrupted` left off, if a transition was cut short.
+    pub(crate) fn new(
+        transition: Transition,
+        closing: bool,
+        interrupted: Option<ActiveTransition>,
+    ) -> Self {
+        let from = match interrupted {
+            Some(interrupted) => interrupted.visibility(),
+            None if closing => 1.,
+            None => 0.,
+        };
+        Self {
+            transition,
+            closing,
+            from,
+            start: None,
+        }
+    }
+
+    pub(crate) fn closing(&self) -> bool {
+        self.closing
+    }
+
+    fn visibility(&self) -> f32 {
+        let elapsed = self.start.map_or(0., |start| start.elapsed().as_secs_f32());
+        let step = match self.transition.duration.as_secs_f32() {
+            0. => 1.,
+            duration => elapsed / duration,
+        };
+        let visibility = if self.closing {
+            self.from - step
+        } else {
+            self.from + step
+        };
+        visibility.clamp(0., 1.)
+    }
+
+    fn finished(&self) -> bool {
+        self.visibility() == if self.closing { 0. } else { 1. }
+    }
+
+    /// Alpha and transform of the frame about to be drawn on a `size` surface, in points.
+    fn frame(&mut self, size: Vec2, anchor: Option<Anchor>) -> (f32, TSTransform) {
+        self.start.get_or_insert_with(Instant::now);
+        let visible = self.transition.easing.apply(self.visibility());
+        match self.transition.kind {
+            TransitionKind::Fade => (visible, TSTransform::IDENTITY),
+            TransitionKind::Slide => {
+                let offset = slide_direction(anchor.unwrap_or(Anchor::empty())) * size;
+                (1., TSTransform::from_translation(offset * (1. - visible)))
+            }
+            TransitionKind::Scale => {
+                let scaling = SCALE_FROM + (1. - SCALE_FROM) * visible;
+                let center = size / 2.;
+                let translation = center - center * scaling;
+                (visible, TSTransform::new(translation, scaling))
+            }
+        }
+    }
+}
+
+/// Unit vector pointing from the surface towards the edge it slides in from.
+fn slide_direction(anchor: Anchor) -> Vec2 {
+    let single = |a: Anchor, b: Anchor| anchor.contains(a) && !anchor.contains(b);
+    if single(Anchor::BOTTOM, Anchor::TOP) {
+        Vec2::DOWN
+    } else if single(Anchor::LEFT, Anchor::RIGHT) {
+        Vec2::LEFT
+    } else if single(Anchor::RIGHT, Anchor::LEFT) {
+        Vec2::RIGHT
+    } else {
+        Vec2::UP
+    }
+}
+
+impl PopupSurface {
+    /// Hidden, or playing the close transition.
+    pub fn is_hidden(&self) -> bool {
+        self.hidden || self.transition.is_some_and(|t| t.closing())
+    }
+
+    /// Hides or shows the surface, playing the transition configured for it first.
+    pub fn set_hidden(&mut self, hide: bool) {
+        if hide == self.is_hidden() {
+            return;
+        }
+        let interrupted = self.transition.take();
+        if hide {
+            match self.layer_opts.close_transition {
+                Some(close) => {
+                    self.transition = Some(ActiveTransition::new(close, true, interrupted))
+                }
+                None => self.apply_hidden(true),
+            }
+        } else {
+            self.apply_hidden(false);
+            self.transition = self
+                .layer_opts
+                .open_transition
+                .map(|open| ActiveTransition::new(open, false, interrupted));
+        }
+        self.egui_state.context().request_repaint();
+    }
+
+    /// Alpha and transform to draw the next frame with.
+    pub(crate) fn transition_frame(&mut self) -> (f32, TSTransform) {
+        let size = {
+            let (width, height) = self.logical_size;
+            self.egui_state
+                .logical_to_points(width as f64, height as f64)
+                .to_vec2()
+        };
+        let anchor = self.layer_opts.anchor;
+        match &mut self.transition {
+            Some(transition) => transition.frame(size, anchor),
+            None => (1., TSTransform::IDENTITY),
+        }
+    }
+
+    /// Called after a frame was presented, keeps the animation going or ends it.
+    pub(crate) fn advance_transition(&mut self) {
+        let Some(transition) = self.transition else {
+            return;
+        };
+        if !transition.finished() {
+            self.egui_state.context().request_repaint();
+            return;
+        }
+        self.transition = None;
+        if transition.closing() {
+            self.apply_hidden(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASINGS: [Easing; 4] = [
+        Easing::Linear,
+        Easing::EaseIn,
+        Easing::EaseOut,
+        Easing::EaseInOut,
+    ];
+
+    fn started(closing: bool, from: f32, elapsed: Duration) -> ActiveTransition {
+        ActiveTransition {
+            transition: Transition::new(TransitionKind::Fade, Duration::from_secs(1))
+                .with_easing(Easing::Linear),
+            closing,
+            from,
+            start: Some(Instant::now() - elapsed),
+        }
+    }
+
+    #[test]
+    fn easings_keep_both_ends() {
+        for easing in EASINGS {
+            assert_eq!(easing.apply(0.), 0., "{:?}", easing);
+            assert_eq!(easing.apply(1.), 1., "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn easings_at_a_quarter() {
+        let cases = [
+            (Easing::Linear, 0.25),
+            (Easing::EaseIn, 0.015625),
+            (Easing::EaseOut, 0.578125),
+            (Easing::EaseInOut, 0.0625),
+        ];
+        for (easing, eased) in cases {
+            assert_eq!(easing.apply(0.25), eased, "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn ease_in_out_is_symmetric() {
+        for t in [0.1, 0.25, 0.4, 0.5] {
+            let sum = Easing::EaseInOut.apply(t) + Easing::EaseInOut.apply(1. - t);
+            assert!((sum - 1.).abs() < 1e-6, "{}", t);
+        }
+    }
+
+    #[test]
+    fn progress_over_time() {
+        let cases = [
+            // closing, from, elapsed ms, visibility
+            (false, 0., 0, 0.),
+            (false, 0., 500, 0.5),
+            (false, 0., 2000, 1.),
+            (false, 0.5, 250, 0.75),
+            (true, 1., 250, 0.75),
+            (true, 0.5, 1000, 0.),
+        ];
+        for (closing, from, elapsed, visibility) in cases {
+            let transition = started(closing, from, Duration::from_millis(elapsed));
+            let actual = transition.visibility();
+            // Time keeps passing while the test runs.
+            assert!((actual - visibility).abs() < 0.05, "{:?}", transition);
+            let end = if closing { 0. } else { 1. };
+            assert_eq!(transition.finished(), visibility == end);
+        }
+    }
+
+    #[test]
+    fn resumes_where_interrupted() {
+        let opening = started(false, 0., Duration::from_millis(300));
+        let close = Transition::new(TransitionKind::Fade, Duration::from_secs(1));
+        let closing = ActiveTransition::new(close, true, Some(opening));
+        assert!((closing.from - 0.3).abs() < 0.05);
+        assert!(closing.closing());
+    }
+
+    #[test]
+    fn zero_duration_finishes_at_once() {
+        let mut transition = started(false, 0., Duration::ZERO);
+        transition.transition.duration = Duration::ZERO;
+        assert!(transition.finished());
+    }
+
+    #[test]
+    fn slides_in_from_anchored_edge() {
+        let cases = [
+            (Anchor::empty(), Vec2::UP),
+            (Anchor::TOP, Vec2::UP),
+            (Anchor::BOTTOM, Vec2::DOWN),
+            (Anchor::LEFT, Vec2::LEFT),
+            (Anchor::RIGHT | Anchor::TOP | Anchor::BOTTOM, Vec2::RIGHT),
+            (Anchor::TOP | Anchor::BOTTOM, Vec2::UP),
+            (Anchor::all(), Vec2::UP),
+        ];
+        for (anchor, direction) in cases {
+            assert_eq!(slide_direction(anchor), direction, "{:?}", anchor);
+        }
+    }
+}
@@ -0,0 +1,484 @@
+//! Types text and key chords into the focused window through `zwp_virtual_keyboard_v1`.
+//!
+//! The protocol requires a keymap before any key, so the keyboard uploads one of its own with a
+//! key for every keysym it types. Keysyms missing from it are added by uploading a new keymap.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Write},
+    os::fd::{AsFd, FromRawFd, OwnedFd},
+    time::Instant,
+};
+
+use bitflags::bitflags;
+use sctk::reexports::protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+use sctk::seat::keyboard::Keysym;
+use tracing::warn;
+use wayland_backend::client::ObjectId;
+use wayland_client::{
+    protocol::wl_keyboard::{KeyState, KeymapFormat},
+    Proxy,
+};
+
+use super::{SurfaceId, WgpuLayerShellState};
+use crate::proto::KeyCode;
+
+/// xkb keycodes are evdev codes offset by 8.
+const EVDEV_OFFSET: u32 = 8;
+/// The highest keycode X11 clients understand, which bounds the keys of one keymap.
+const MAX_KEYCODE: u32 = 255;
+const MAX_KEYS: usize = (MAX_KEYCODE - EVDEV_OFFSET) as usize;
+
+bitflags! {
+    /// Modifiers held down while the key of a [`KeyChord`] is pressed.
+    ///
+    /// The bits are the masks of the xkb real modifiers.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct KeyModifiers: u32 {
+        const SHIFT = 1 << 0;
+        const CTRL = 1 << 2;
+        /// `Mod1`
+        const ALT = 1 << 3;
+        /// `Mod4`, the super or windows key.
+        const LOGO = 1 << 6;
+    }
+}
+
+/// The key of a [`KeyChord`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordKey {
+    /// A key of a keyboard with the US layout, by its evdev code.
+    Code(KeyCode),
+    Keysym(Keysym),
+}
+
+impl ChordKey {
+    fn keysym(self) -> Option<Keysym> {
+        match self {
+            ChordKey::Code(code) => keycode_to_keysym(code),
+            ChordKey::Keysym(keysym) => Some(keysym),
+        }
+    }
+}
+
+impl From<KeyCode> for ChordKey {
+    fn from(code: KeyCode) -> Self {
+        ChordKey::Code(code)
+    }
+}
+
+impl From<Keysym> for ChordKey {
+    fn from(keysym: Keysym) -> Self {
+        ChordKey::Keysym(keysym)
+    }
+}
+
+impl From<char> for ChordKey {
+    fn from(ch: char) -> Self {
+        ChordKey::Keysym(char_to_keysym(ch))
+    }
+}
+
+/// A key pressed and released while holding modifiers, see
+/// [`crate::application::Msg::SendKeys`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyChord {
+    pub modifiers: KeyModifiers,
+    pub key: ChordKey,
+}
+
+impl KeyChord {
+    pub fn new(modifiers: KeyModifiers, key: impl Into<ChordKey>) -> Self {
+        Self {
+            modifiers,
+            key: key.into(),
+        }
+    }
+
+    /// The key alone.
+    pub fn key(key: impl Into<ChordKey>) -> Self {
+        Self::new(KeyModifiers::empty(), key)
+    }
+}
+
+/// A virtual keyboard on a seat, with the keymap it uploaded.
+pub struct VirtualKeyboard {
+    keyboard: ZwpVirtualKeyboardV1,
+    /// Keys of the keymap, the one at index `i` has the evdev code `i + 1`.
+    keysyms: Vec<Keysym>,
+    /// Start of the key timestamps.
+    created: Instant,
+}
+
+impl VirtualKeyboard {
+    pub(crate) fn new(keyboard: ZwpVirtualKeyboardV1) -> Self {
+        Self {
+            keyboard,
+            keysyms: Vec::new(),
+            created: Instant::now(),
+        }
+    }
+
+    /// Presses and releases every key in order.
+    fn send(&mut self, strokes: &[(KeyModifiers, Keysym)]) -> io::Result<()> {
+        let mut rest = strokes;
+        while !rest.is_empty() {
+            let typed = self.prepare_keymap(rest)?;
+            for &(modifiers, keysym) in &rest[..typed] {
+                // Present, prepare_keymap added it.
+                if let Some(index) = self.keysyms.iter().position(|k| *k == keysym) {
+                    self.stroke(modifiers, index as u32 + 1);
+                }
+            }
+            rest = &rest[typed..];
+        }
+        Ok(())
+    }
+
+    /// Makes sure the keymap has keys for as many of the `strokes` as fit, returns how many.
+    fn prepare_keymap(&mut self, strokes: &[(KeyModifiers, Keysym)]) -> io::Result<usize> {
+        let (keysyms, count) = plan_keymap(&self.keysyms, strokes);
+        if keysyms != self.keysyms {
+            let file = keymap_file(&keymap(&keysyms))?;
+            let size = file.metadata()?.len() as u32;
+            self.keyboard
+                .keymap(KeymapFormat::XkbV1 as u32, file.as_fd(), size);
+            self.keysyms = keysyms;
+        }
+        Ok(count)
+    }
+
+    fn stroke(&self, modifiers: KeyModifiers, key: u32) {
+        let time = self.created.elapsed().as_millis() as u32;
+        if !modifiers.is_empty() {
+            self.keyboard.modifiers(modifiers.bits(), 0, 0, 0);
+        }
+        self.keyboard.key(time, key, KeyState::Pressed as u32);
+        self.keyboard.key(time, key, KeyState::Released as u32);
+        if !modifiers.is_empty() {
+            self.keyboard.modifiers(0, 0, 0, 0);
+        }
+    }
+
+    pub(crate) fn destroy(self) {
+        self.keyboard.destroy();
+    }
+}
+
+/// The keys of a keymap extending `current` for as many of the `strokes` as fit, with how
+/// many fit.
+fn plan_keymap(current: &[Keysym], strokes: &[(KeyModifiers, Keysym)]) -> (Vec<Keysym>, usize) {
+    let mut keysyms = current.to_vec();
+    let mut count = 0;
+    for &(_, keysym) in strokes {
+        if !keysyms.contains(&keysym) {
+            if keysyms.len() == MAX_KEYS {
+                if count > 0 {
+                    break;
+                }
+                // Full of keys typed earlier, start over.
+                keysyms.clear();
+            }
+            keysyms.push(keysym);
+        }
+        count += 1;
+    }
+    (keysyms, count)
+}
+
+/// An xkb keymap with one single level key per keysym.
+fn keymap(keysyms: &[Keysym]) -> String {
+    let mut keycodes = String::new();
+    let mut symbols = String::new();
+    for (index, keysym) in keysyms.iter().enumerate() {
+        let code = index as u32 + 1 + EVDEV_OFFSET;
+        let _ = writeln!(keycodes, "        <K{code}> = {code};");
+        let _ = writeln!(
+            symbols,
+            "        key <K{code}> {{ [ {:#x} ] }};",
+            keysym.raw()
+        );
+        if let Some(modifier) = modifier_of(*keysym) {
+            let _ = writeln!(symbols, "        modifier_map {modifier} {{ <K{code}> }};");
+        }
+    }
+    format!(
+        "xkb_keymap {{
+    xkb_keycodes \"wpopup\" {{
+        minimum = {EVDEV_OFFSET};
+        maximum = {MAX_KEYCODE};
+{keycodes}    }};
+    xkb_types \"wpopup\" {{ include \"complete\" }};
+    xkb_compatibility \"wpopup\" {{ include \"complete\" }};
+    xkb_symbols \"wpopup\" {{
+{symbols}    }};
+}};
+"
+    )
+}
+
+/// The real modifier a modifier key sets while held.
+fn modifier_of(keysym: Keysym) -> Option<&'static str> {
+    match keysym {
+        Keysym::Shift_L | Keysym::Shift_R => Some("Shift"),
+        Keysym::Caps_Lock => Some("Lock"),
+        Keysym::Control_L | Keysym::Control_R => Some("Control"),
+        Keysym::Alt_L | Keysym::Alt_R | Keysym::Meta_L | Keysym::Meta_R => Some("Mod1"),
+        Keysym::Super_L | Keysym::Super_R => Some("Mod4"),
+        _ => None,
+    }
+}
+
+/// Writes the keymap to a memfd, NUL terminated as the protocol expects.
+fn keymap_file(keymap: &str) -> io::Result<File> {
+    // SAFETY: the name is a NUL terminated C-string literal, valid for the whole call.
+    let fd = unsafe { libc::memfd_create(c"wpopup-keymap".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: memfd_create returned a new descriptor nobody else owns.
+    let mut file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    file.write_all(keymap.as_bytes())?;
+    file.write_all(&[0])?;
+    Ok(file)
+}
+
+fn char_to_keysym(ch: char) -> Keysym {
+    match ch {
+        '\n' | '\r' => Keysym::Return,
+        ch => Keysym::from_char(ch),
+    }
+}
+
+/// The keysym an evdev key produces on a US layout keyboard, without modifiers.
+fn keycode_to_keysym(code: KeyCode) -> Option<Keysym> {
+    const KEYS: &[(KeyCode, Keysym)] = &[
+        (KeyCode::KEY_ESC, Keysym::Escape),
+        (KeyCode::KEY_1, Keysym::_1),
+        (KeyCode::KEY_2, Keysym::_2),
+        (KeyCode::KEY_3, Keysym::_3),
+        (KeyCode::KEY_4, Keysym::_4),
+        (KeyCode::KEY_5, Keysym::_5),
+        (KeyCode::KEY_6, Keysym::_6),
+        (KeyCode::KEY_7, Keysym::_7),
+        (KeyCode::KEY_8, Keysym::_8),
+        (KeyCode::KEY_9, Keysym::_9),
+        (KeyCode::KEY_0, Keysym::_0),
+        (KeyCode::KEY_MINUS, Keysym::minus),
+        (KeyCode::KEY_EQUAL, Keysym::equal),
+        (KeyCode::KEY_BACKSPACE, Keysym::BackSpace),
+        (KeyCode::KEY_TAB, Keysym::Tab),
+        (KeyCode::KEY_Q, Keysym::q),
+        (KeyCode::KEY_W, Keysym::w),
+        (KeyCode::KEY_E, Keysym::e),
+        (KeyCode::KEY_R, Keysym::r),
+        (KeyCode::KEY_T, Keysym::t),
+        (KeyCode::KEY_Y, Keysym::y),
+        (KeyCode::KEY_U, Keysym::u),
+        (KeyCode::KEY_I, Keysym::i),
+        (KeyCode::KEY_O, Keysym::o),
+        (KeyCode::KEY_P, Keysym::p),
+        (KeyCode::KEY_LEFTBRACE, Keysym::bracketleft),
+        (KeyCode::KEY_RIGHTBRACE, Keysym::bracketright),
+        (KeyCode::KEY_ENTER, Keysym::Return),
+        (KeyCode::KEY_LEFTCTRL, Keysym::Control_L),
+        (KeyCode::KEY_A, Keysym::a),
+        (KeyCode::KEY_S, Keysym::s),
+        (KeyCode::KEY_D, Keysym::d),
+        (KeyCode::KEY_F, Keysym::f),
+        (KeyCode::KEY_G, Keysym::g),
+        (KeyCode::KEY_H, Keysym::h),
+        (KeyCode::KEY_J, Keysym::j),
+        (KeyCode::KEY_K, Keysym::k),
+        (KeyCode::KEY_L, Keysym::l),
+        (KeyCode::KEY_SEMICOLON, Keysym::semicolon),
+        (KeyCode::KEY_APOSTROPHE, Keysym::apostrophe),
+        (KeyCode::KEY_GRAVE, Keysym::grave),
+        (KeyCode::KEY_LEFTSHIFT, Keysym::Shift_L),
+        (KeyCode::KEY_BACKSLASH, Keysym::backslash),
+        (KeyCode::KEY_Z, Keysym::z),
+        (KeyCode::KEY_X, Keysym::x),
+        (KeyCode::KEY_C, Keysym::c),
+        (KeyCode::KEY_V, Keysym::v),
+        (KeyCode::KEY_B, Keysym::b),
+        (KeyCode::KEY_N, Keysym::n),
+        (KeyCode::KEY_M, Keysym::m),
+        (KeyCode::KEY_COMMA, Keysym::comma),
+        (KeyCode::KEY_DOT, Keysym::period),
+        (KeyCode::KEY_SLASH, Keysym::slash),
+        (KeyCode::KEY_RIGHTSHIFT, Keysym::Shift_R),
+        (KeyCode::KEY_LEFTALT, Keysym::Alt_L),
+        (KeyCode::KEY_SPACE, Keysym::space),
+        (KeyCode::KEY_CAPSLOCK, Keysym::Caps_Lock),
+        (KeyCode::KEY_F1, Keysym::F1),
+        (KeyCode::KEY_F2, Keysym::F2),
+        (KeyCode::KEY_F3, Keysym::F3),
+        (KeyCode::KEY_F4, Keysym::F4),
+        (KeyCode::KEY_F5, Keysym::F5),
+        (KeyCode::KEY_F6, Keysym::F6),
+        (KeyCode::KEY_F7, Keysym::F7),
+        (KeyCode::KEY_F8, Keysym::F8),
+        (KeyCode::KEY_F9, Keysym::F9),
+        (KeyCode::KEY_F10, Keysym::F10),
+        (KeyCode::KEY_F11, Keysym::F11),
+        (KeyCode::KEY_F12, Keysym::F12),
+        (KeyCode::KEY_RIGHTCTRL, Keysym::Control_R),
+        (KeyCode::KEY_SYSRQ, Keysym::Print),
+        (KeyCode::KEY_RIGHTALT, Keysym::Alt_R),
+        (KeyCode::KEY_HOME, Keysym::Home),
+        (KeyCode::KEY_UP, Keysym::Up),
+        (KeyCode::KEY_PAGEUP, Keysym::Prior),
+        (KeyCode::KEY_LEFT, Keysym::Left),
+        (KeyCode::KEY_RIGHT, Keysym::Right),
+        (KeyCode::KEY_END, Keysym::End),
+        (KeyCode::KEY_DOWN, Keysym::Down),
+        (KeyCode::KEY_PAGEDOWN, Keysym::Next),
+        (KeyCode::KEY_INSERT, Keysym::Insert),
+        (KeyCode::KEY_DELETE, Keysym::Delete),
+        (KeyCode::KEY_LEFTMETA, Keysym::Super_L),
+        (KeyCode::KEY_RIGHTMETA, Keysym::Super_R),
+        (KeyCode::KEY_COMPOSE, Keysym::Menu),
+    ];
+    KEYS.iter().find(|(c, _)| *c == code).map(|&(_, k)| k)
+}
+
+impl WgpuLayerShellState {
+    /// Types `text` into the window with keyboard focus.
+    ///
+    /// Uses the seat typing into surface `id`, or the first one. Keys typed while a surface
+    /// of this app still has keyboard focus go to that surface, so hide it first with
+    /// [`super::HideStrategy::Unmap`] to type into the previously focused window.
+    pub fn type_text(&mut self, id: SurfaceId, text: &str) {
+        let strokes: Vec<_> = text
+            .chars()
+            .map(char_to_keysym)
+            .filter(|keysym| *keysym != Keysym::NoSymbol)
+            .map(|keysym| (KeyModifiers::empty(), keysym))
+            .collect();
+        self.send_strokes(id, &strokes);
+    }
+
+    /// Presses the chords in order, like [`WgpuLayerShellState::type_text`].
+    pub fn send_keys(&mut self, id: SurfaceId, chords: &[KeyChord]) {
+        let strokes: Vec<_> = chords
+            .iter()
+            .filter_map(|chord| match chord.key.keysym() {
+                Some(keysym) => Some((chord.modifiers, keysym)),
+                None => {
+                    warn!("no keysym for {:?}", chord.key);
+                    None
+                }
+            })
+            .collect();
+        self.send_strokes(id, &strokes);
+    }
+
+    fn send_strokes(&mut self, id: SurfaceId, strokes: &[(KeyModifiers, Keysym)]) {
+        let Some(seat) = self.typing_seat(id) else {
+            warn!("no seat to type on");
+            return;
+        };
+        let Some(keyboard) = self.virtual_keyboard(&seat) else {
+            warn!("zwp_virtual_keyboard_manager_v1 not available");
+            return;
+        };
+        if let Err(e) = keyboard.send(strokes) {
+            warn!("could not upload the virtual keyboard keymap: {}", e);
+        }
+    }
+
+    /// The seat typing into surface `id`, otherwise the first one with a keyboard.
+    fn typing_seat(&self, id: SurfaceId) -> Option<ObjectId> {
+        // In the order the compositor advertised them, the map has none.
+        let seats: Vec<_> = self
+            .seat_state
+            .seats()
+            .map(|seat| seat.id())
+            .filter(|seat| self.seat_map.contains_key(seat))
+            .collect();
+        seats
+            .iter()
+            .find(|seat| self.seat_map[*seat].keyboard_focus == Some(id))
+            .or_else(|| {
+                seats
+                    .iter()
+                    .find(|seat| self.seat_map[*seat].keyboard.is_some())
+            })
+            .or(seats.first())
+            .cloned()
+    }
+
+    /// The virtual keyboard of a seat, created on first use.
+    fn virtual_keyboard(&mut self, seat: &ObjectId) -> Option<&mut VirtualKeyboard> {
+        let manager = self.virtual_keyboard_manager.as_ref()?;
+        let per_seat = self.seat_map.get_mut(seat)?;
+        let keyboard = per_seat.virtual_keyboard.get_or_insert_with(|| {
+            VirtualKeyboard::new(manager.create_virtual_keyboard(
+                &per_seat.seat,
+                &self.queue_handle,
+                (),
+            ))
+        });
+        Some(keyboard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` keysyms of unicode characters, starting at `first`.
+    fn strokes(first: u32, count: usize) -> Vec<(KeyModifiers, Keysym)> {
+        (first..)
+            .take(count)
+            .map(|c| (KeyModifiers::empty(), Keysym::new(0x0100_0000 + c)))
+            .collect()
+    }
+
+    fn keysyms(strokes: &[(KeyModifiers, Keysym)]) -> Vec<Keysym> {
+        strokes.iter().map(|&(_, keysym)| keysym).collect()
+    }
+
+    #[test]
+    fn keymap_has_a_key_per_keysym() {
+        let keymap = keymap(&[Keysym::a, Keysym::Return, Keysym::Shift_L]);
+        assert!(keymap.contains("<K9> = 9;"));
+        assert!(keymap.contains("key <K9> { [ 0x61 ] };"));
+        assert!(keymap.contains("key <K10> { [ 0xff0d ] };"));
+        assert!(keymap.contains("key <K11> { [ 0xffe1 ] };"));
+        assert!(keymap.contains("modifier_map Shift { <K11> };"));
+        assert!(!keymap.contains("<K12>"));
+    }
+
+    #[test]
+    fn keeps_keys_already_in_keymap() {
+        let typed = strokes(0x100, 3);
+        let (keys, count) = plan_keymap(&keysyms(&typed), &typed[1..]);
+        assert_eq!(keys, keysyms(&typed));
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn splits_strokes_past_max_keys() {
+        let typed = strokes(0x100, MAX_KEYS + 10);
+        let (keys, count) = plan_keymap(&[], &typed);
+        assert_eq!(keys, keysyms(&typed[..MAX_KEYS]));
+        assert_eq!(count, MAX_KEYS);
+
+        let (keys, count) = plan_keymap(&keys, &typed[count..]);
+        assert_eq!(keys, keysyms(&typed[MAX_KEYS..]));
+        assert_eq!(count, 10);
+    }
+
+    #[test]
+    fn maps_us_layout_keycodes() {
+        assert_eq!(keycode_to_keysym(KeyCode::KEY_A), Some(Keysym::a));
+        assert_eq!(keycode_to_keysym(KeyCode::KEY_ENTER), Some(Keysym::Return));
+        assert_eq!(
+            keycode_to_keysym(KeyCode::KEY_LEFTMETA),
+            Some(Keysym::Super_L)
+        );
+        assert_eq!(keycode_to_keysym(KeyCode::KEY_F13), None);
+    }
+}
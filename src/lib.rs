@@ -6,6 +6,7 @@ use layer_shell::LayerShellOptions;
 
 use crate::{
     application::{EvRx, MsgQueue},
+    errors::InitError,
     layer_shell::WgpuLayerShellState,
 };
 
@@ -16,11 +17,13 @@ pub(crate) mod wgpu_state;
 pub use egui_chinese_font;
 pub mod errors;
 pub mod proto;
+pub mod runtime;
 pub mod text_input;
 pub use async_bincode;
 pub use egui;
 pub use exponential_backoff;
 pub use flume;
+pub use runtime::{run, spawn_on_thread};
 pub use eframe;
 pub use sctk::shell::wlr_layer::Layer;
 
@@ -37,7 +40,8 @@ pub trait App {
     fn update(&mut self, ctx: &egui::Context);
 
     // fn save(&mut self, _storage: &mut dyn Storage) {}
-    // fn on_exit(&mut self) {}
+    /// Called before the app is dropped, when its surface is closed or the event loop exits.
+    fn on_exit(&mut self) {}
     // fn auto_save_interval(&self) -> std::time::Duration {
     //     std::time::Duration::from_secs(30)
     // }
@@ -50,16 +54,16 @@ pub trait App {
 pub fn run_layer(
     options: LayerShellOptions,
     app_creator: AppCreator,
-) -> (MsgQueue, WgpuLayerShellApp) {
-    let (q, r, app) = WgpuLayerShellApp::new(options, app_creator);
+) -> Result<(MsgQueue, WgpuLayerShellApp), InitError> {
+    let (q, _, app) = WgpuLayerShellApp::new(options, app_creator)?;
 
-    (q, app)
+    Ok((q, app))
 }
 
 pub fn run_layer_simple(
     options: LayerShellOptions,
     update_fun: impl FnMut(&egui::Context, &MsgQueue) + 'static,
-) -> (MsgQueue, WgpuLayerShellApp) {
+) -> Result<(MsgQueue, WgpuLayerShellApp), InitError> {
     struct SimpleLayerWrapper<U> {
         update_fun: U,
         msg: MsgQueue,
@@ -75,15 +79,15 @@ pub fn run_layer_simple(
     let (sx, e) = run_layer(
         options,
         Box::new(|a, b, c| Ok(Box::new(SimpleLayerWrapper { update_fun, msg: b }))),
-    );
+    )?;
 
-    (sx, e)
+    Ok((sx, e))
 }
 
 pub fn run_layer_cjk(
     options: LayerShellOptions,
     update_fun: impl FnMut(&egui::Context, &MsgQueue) + 'static,
-) -> (MsgQueue, WgpuLayerShellApp) {
+) -> Result<(MsgQueue, WgpuLayerShellApp), InitError> {
     struct SimpleLayerWrapper<U> {
         update_fun: U,
         msg: MsgQueue,
@@ -102,7 +106,7 @@ pub fn run_layer_cjk(
     let (sx, e) = run_layer(
         options,
         Box::new(|a, b, c| Ok(Box::new(SimpleLayerWrapper { update_fun, msg: b }))),
-    );
+    )?;
 
-    (sx, e)
+    Ok((sx, e))
 }
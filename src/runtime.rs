@@ -0,0 +1,92 @@
+//! Runs the layer shell loop on a thread of its own, for apps written in async code.
+
+use std::{io, thread};
+
+use futures::Stream;
+use tokio::sync::oneshot;
+
+use crate::{
+    application::{EvRx, ExitReason, Msg, MsgQueue, SurfaceCreator, WPEvent, WgpuLayerShellApp},
+    errors::InitError,
+    layer_shell::LayerShellOptions,
+    Result,
+};
+
+/// Talks to a layer shell loop started with [`spawn_on_thread`].
+pub struct LayerShellHandle {
+    msg: MsgQueue,
+    ev: EvRx,
+    exited: oneshot::Receiver<Result<ExitReason>>,
+}
+
+impl LayerShellHandle {
+    /// Queue of the first surface. Sending never blocks.
+    pub fn msg_queue(&self) -> &MsgQueue {
+        &self.msg
+    }
+
+    /// Fails once the loop exited.
+    pub fn send(&self, msg: Msg) -> Result {
+        self.msg
+            .send(msg)
+            .map_err(|_| anyhow::anyhow!("the layer shell loop has exited"))
+    }
+
+    /// Events of the loop as a stream.
+    ///
+    /// The handle gets every event, whatever the apps receive. Streams of the same handle
+    /// share its queue though, each event goes to whichever asks first.
+    pub fn events(&self) -> impl Stream<Item = WPEvent> {
+        self.ev.clone().into_stream()
+    }
+
+    /// The next event, `None` once the loop is gone.
+    pub async fn next_event(&self) -> Option<WPEvent> {
+        self.ev.recv_async().await.ok()
+    }
+
+    /// Waits for the loop to return from [`WgpuLayerShellApp::run_forever`].
+    pub async fn exited(self) -> Result<ExitReason> {
+        match self.exited.await {
+            Ok(exited) => exited,
+            Err(_) => Err(anyhow::anyhow!("the layer shell thread panicked")),
+        }
+    }
+}
+
+/// Creates the app on a new thread and runs its loop there.
+///
+/// Resolves once the first surface was created, or failed to.
+pub async fn spawn_on_thread(
+    options: LayerShellOptions,
+    creator: SurfaceCreator,
+) -> Result<LayerShellHandle, InitError> {
+    let (init_sx, init_rx) = oneshot::channel();
+    let (exit_sx, exit_rx) = oneshot::channel();
+    thread::Builder::new()
+        .name("wpopup".to_owned())
+        .spawn(move || match WgpuLayerShellApp::new(options, creator) {
+            Ok((msg, ev, app)) => {
+                let _ = init_sx.send(Ok((msg, ev)));
+                let _ = exit_sx.send(app.run_forever());
+            }
+            Err(e) => {
+                let _ = init_sx.send(Err(e));
+            }
+        })
+        .map_err(InitError::Thread)?;
+
+    let (msg, ev) = init_rx
+        .await
+        .map_err(|_| InitError::Thread(io::Error::other("the layer shell thread panicked")))??;
+    Ok(LayerShellHandle {
+        msg,
+        ev,
+        exited: exit_rx,
+    })
+}
+
+/// Runs the app on a thread of its own until it exits.
+pub async fn run(options: LayerShellOptions, creator: SurfaceCreator) -> Result<ExitReason> {
+    spawn_on_thread(options, creator).await?.exited().await
+}
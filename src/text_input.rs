@@ -11,8 +11,9 @@ use sctk::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_v3::
     ContentHint, ContentPurpose, Event as TextInputEvent, ZwpTextInputV3,
 };
 use tracing::{info, warn};
+use wayland_backend::client::ObjectId;
 
-use crate::layer_shell::{pixels_per_point, WgpuLayerShellState};
+use crate::layer_shell::{SurfaceId, WgpuLayerShellState};
 
 #[derive(Debug)]
 pub struct TextInputState {
@@ -68,13 +69,15 @@ impl Dispatch<ZwpTextInputV3, TextInputData, WgpuLayerShellState> for TextInputS
             TextInputEvent::Enter { surface } => {
                 text_input_data.surface = Some(surface);
 
-                if state.ime_allowed() {
-                    text_input.enable();
-                    text_input.set_content_type_by_purpose(state.ime_purpose);
-                    text_input.commit();
-                }
-
                 state.text_input_entered(text_input);
+
+                // The keyboard may have entered first, the IME being enabled already.
+                let client_state = state
+                    .seat_of_text_input(text_input)
+                    .and_then(|seat| state.seat_map[&seat].text_input_state.as_ref());
+                if let Some(client_state) = client_state {
+                    text_input.set_state(Some(client_state), true);
+                }
             }
             TextInputEvent::Leave { surface } => {
                 text_input_data.surface = None;
@@ -84,7 +87,9 @@ impl Dispatch<ZwpTextInputV3, TextInputData, WgpuLayerShellState> for TextInputS
                 text_input.commit();
 
                 state.text_input_left(text_input);
-                state.egui_state.ime_event_disable();
+                if let Some(surface) = state.surface_of_mut(&surface) {
+                    surface.egui_state.ime_event_disable();
+                }
             }
             TextInputEvent::PreeditString {
                 text,
@@ -119,6 +124,13 @@ impl Dispatch<ZwpTextInputV3, TextInputData, WgpuLayerShellState> for TextInputS
                 });
             }
             TextInputEvent::Done { .. } => {
+                let Some(surface) = text_input_data.surface.clone() else {
+                    return;
+                };
+                let Some(surface) = state.surface_of_mut(&surface) else {
+                    return;
+                };
+
                 // The events are sent to the user separately, so
                 // CAUTION: events must always arrive in the order compatible with the application
                 // order specified by the text-input-v3 protocol:
@@ -131,39 +143,46 @@ impl Dispatch<ZwpTextInputV3, TextInputData, WgpuLayerShellState> for TextInputS
                 // 5. Insert new preedit text in cursor position.
                 // 6. Place cursor inside preedit text.
 
-                if let Some(DeleteSurroundingText { before, after }) =
-                    text_input_data.pending_delete
-                {
-                    // state.events_sink.push_window_event(
-                    //     WindowEvent::Ime(Ime::DeleteSurrounding {
-                    //         before_bytes: before,
-                    //         after_bytes: after,
-                    //     }),
-                    //     window_id,
-                    // );
+                // 1. and 2. The preedit and the text to delete are selected, egui replaces the
+                // selection with an empty preedit.
+                let (before, after) = text_input_data
+                    .pending_delete
+                    .take()
+                    .map_or((0, 0), |delete| (delete.before, delete.after));
+                if surface.egui_state.select_ime_replacement(before, after) {
+                    surface
+                        .egui_state
+                        .push_event(egui::Event::Ime(egui::ImeEvent::Preedit(String::new())));
                 }
 
                 // Clear preedit, unless all we'll be doing next is sending a new preedit.
                 if text_input_data.pending_commit.is_some()
                     || text_input_data.pending_preedit.is_none()
                 {
-                    state.egui_state.ime_event_disable();
+                    surface.egui_state.ime_event_disable();
                 }
 
                 // Send `Commit`.
                 if let Some(text) = text_input_data.pending_commit.take() {
-                    state
+                    surface
                         .egui_state
                         .push_event(egui::Event::Ime(egui::ImeEvent::Commit(text)));
                 }
 
                 // Send preedit.
-                if let Some(preedit) = text_input_data.pending_preedit.take() {
+                if let Some(preedit) = text_input_data
+                    .pending_preedit
+                    .take()
+                    .filter(|preedit| !preedit.text.is_empty())
+                {
                     let cursor_range = preedit
                         .cursor_begin
                         .map(|b| (b, preedit.cursor_end.unwrap_or(b)));
-                    state.egui_state.ime_event_enable();
-                    state
+                    surface.egui_state.ime_event_enable();
+                    surface
+                        .egui_state
+                        .set_ime_preedit(&preedit.text, cursor_range);
+                    surface
                         .egui_state
                         .push_event(egui::Event::Ime(egui::ImeEvent::Preedit(preedit.text)));
                 }
@@ -471,55 +490,70 @@ bitflags! {
 }
 
 impl WgpuLayerShellState {
+    /// Scale factor of the surface holding keyboard focus, which is where text input happens.
     pub fn scale_factor(&self) -> f64 {
-        1.0
+        self.keyboard_focus
+            .and_then(|id| self.surfaces.get(&id))
+            .map_or(1.0, |s| s.scale_factor())
     }
 
-    pub fn pixels_per_point(&self) -> f32 {
-        pixels_per_point(self.egui_state.context(), self.scale_factor() as f32) as f32
+    pub fn pixels_per_point(&self, id: SurfaceId) -> f32 {
+        self.surfaces
+            .get(&id)
+            .map_or(1.0, |s| s.pixels_per_point())
     }
     /// Register text input on the top-level.
     #[inline]
     pub fn text_input_entered(&mut self, text_input: &ZwpTextInputV3) {
-        if !self.text_inputs.iter().any(|t| t == text_input) {
-            self.text_inputs.push(text_input.clone());
-        }
+        self.set_text_input_entered(text_input, true);
     }
 
     /// The text input left the top-level.
     #[inline]
     pub fn text_input_left(&mut self, text_input: &ZwpTextInputV3) {
-        if let Some(position) = self.text_inputs.iter().position(|t| t == text_input) {
-            self.text_inputs.remove(position);
+        self.set_text_input_entered(text_input, false);
+    }
+
+    fn set_text_input_entered(&mut self, text_input: &ZwpTextInputV3, entered: bool) {
+        let seat = self.seat_of_text_input(text_input);
+        if let Some(per_seat) = seat.and_then(|seat| self.seat_map.get_mut(&seat)) {
+            per_seat.text_input_entered = entered;
         }
     }
 
-    /// Atomically update input method state.
+    /// Atomically update the input method state of a seat.
     ///
     /// Returns `None` if an input method state haven't changed. Alternatively `Some(true)` and
     /// `Some(false)` is returned respectfully.
     pub fn request_ime_update(
         &mut self,
+        seat: &ObjectId,
         request: ImeRequest,
     ) -> Result<Option<bool>, ImeRequestError> {
+        let Some(per_seat) = self.seat_map.get_mut(seat) else {
+            return Err(ImeRequestError::NotSupported);
+        };
+        let scale_factor = per_seat
+            .keyboard_focus
+            .and_then(|id| self.surfaces.get(&id))
+            .map_or(1.0, |s| s.scale_factor());
         let state_change = match request {
             ImeRequest::Enable(enable) => {
                 let (capabilities, request_data) = enable.into_raw();
 
-                if self.window_text_input_state.is_some() {
+                if per_seat.text_input_state.is_some() {
                     return Err(ImeRequestError::AlreadyEnabled);
                 }
 
-                self.text_input_state = Some(TextInputClientState::new(
+                per_seat.text_input_state = Some(TextInputClientState::new(
                     capabilities,
                     request_data,
-                    self.scale_factor(),
+                    scale_factor,
                 ));
                 true
             }
             ImeRequest::Update(request_data) => {
-                let scale_factor = self.scale_factor();
-                if let Some(text_input_state) = self.text_input_state.as_mut() {
+                if let Some(text_input_state) = per_seat.text_input_state.as_mut() {
                     text_input_state.update(request_data, scale_factor);
                 } else {
                     return Err(ImeRequestError::NotEnabled);
@@ -527,23 +561,23 @@ impl WgpuLayerShellState {
                 false
             }
             ImeRequest::Disable => {
-                self.window_text_input_state = None;
+                per_seat.text_input_state = None;
                 true
             }
         };
 
-        // Only one input method may be active per (seat, surface),
-        // but there may be multiple seats focused on a surface,
-        // resulting in multiple text input objects.
-        //
-        // WARNING: this doesn't actually handle different seats with independent cursors. There's
-        // no API to set a per-seat input method state, so they all share a single state.
-        for text_input in &self.text_inputs {
-            text_input.set_state(self.text_input_state.as_ref(), state_change);
+        // Only one input method may be active per (seat, surface), the text input of the seat
+        // is only sent state while it is on one of the surfaces.
+        if let Some(text_input) = per_seat
+            .text_input
+            .as_ref()
+            .filter(|_| per_seat.text_input_entered)
+        {
+            text_input.set_state(per_seat.text_input_state.as_ref(), state_change);
         }
 
         if state_change {
-            Ok(Some(self.window_text_input_state.is_some()))
+            Ok(Some(per_seat.text_input_state.is_some()))
         } else {
             Ok(None)
         }
@@ -552,6 +586,37 @@ impl WgpuLayerShellState {
 
 pub type TextInputClientState = ClientState;
 
+/// The request taking the IME from `current` to `desired`, `None` when it is there already.
+///
+/// `desired` is `None` while no text is edited. Otherwise it holds the data of all
+/// [`client_capabilities`], which the IME is enabled with.
+pub fn ime_transition(
+    current: Option<&ClientState>,
+    desired: Option<ImeRequestData>,
+    scale_factor: f64,
+) -> Option<ImeRequest> {
+    match (current, desired) {
+        (None, None) => None,
+        (Some(_), None) => Some(ImeRequest::Disable),
+        (None, Some(data)) => {
+            ImeEnableRequest::new(client_capabilities(), data).map(ImeRequest::Enable)
+        }
+        (Some(current), Some(data)) => {
+            let mut updated = current.clone();
+            updated.update(data.clone(), scale_factor);
+            (updated != *current).then_some(ImeRequest::Update(data))
+        }
+    }
+}
+
+/// Everything egui tells about the text being edited.
+pub fn client_capabilities() -> ImeCapabilities {
+    ImeCapabilities::new()
+        .with_hint_and_purpose()
+        .with_cursor_area()
+        .with_surrounding_text()
+}
+
 impl ClientState {
     pub fn new(
         capabilities: ImeCapabilities,
@@ -562,7 +627,7 @@ impl ClientState {
             capabilities,
             content_type: Default::default(),
             cursor_area: Default::default(),
-            surrounding_text: ImeSurroundingText::new(String::new(), 0, 0).unwrap(),
+            surrounding_text: ImeSurroundingText::empty(),
         };
 
         let unsupported_flags = capabilities
@@ -844,7 +909,7 @@ impl ImeSurroundingText {
     /// obtained by:
     ///
     /// ```
-    /// # use winit_core::window::ImeSurroundingText;
+    /// # use wpopup::text_input::ImeSurroundingText;
     /// let s = ImeSurroundingText::new("foobar".into(), 3, 3).unwrap();
     /// ```
     ///
@@ -881,6 +946,15 @@ impl ImeSurroundingText {
         })
     }
 
+    /// No text around the caret.
+    pub fn empty() -> Self {
+        Self {
+            text: String::new(),
+            cursor: 0,
+            anchor: 0,
+        }
+    }
+
     /// Consumes the object, releasing the text string only.
     /// Use this call in the backend to avoid an extra clone when submitting the surrounding text.
     pub fn into_text(self) -> String {
@@ -951,7 +1025,7 @@ impl ImeRequestData {
     ///
     /// ```no_run
     /// # use dpi::{LogicalPosition, PhysicalPosition, LogicalSize, PhysicalSize};
-    /// # use winit_core::window::ImeRequestData;
+    /// # use wpopup::text_input::ImeRequestData;
     /// # fn scope(ime_request_data: ImeRequestData) {
     /// // Specify the position in logical dimensions like this:
     /// let ime_request_data = ime_request_data.with_cursor_area(
@@ -995,3 +1069,115 @@ impl ImeRequestData {
 
 delegate_dispatch!(WgpuLayerShellState: [ZwpTextInputManagerV3: GlobalData] => TextInputState);
 delegate_dispatch!(WgpuLayerShellState: [ZwpTextInputV3: TextInputData] => TextInputState);
+
+#[cfg(test)]
+mod tests {
+    use dpi::{LogicalPosition, LogicalSize};
+
+    use super::*;
+
+    fn editing(x: f64, text: &str) -> ImeRequestData {
+        ImeRequestData::default()
+            .with_hint_and_purpose(ImeHint::NONE, ImePurpose::Normal)
+            .with_cursor_area(
+                LogicalPosition::new(x, 10.).into(),
+                LogicalSize::new(100., 20.).into(),
+            )
+            .with_surrounding_text(
+                ImeSurroundingText::new(text.into(), text.len(), text.len()).unwrap(),
+            )
+    }
+
+    fn enabled(data: ImeRequestData) -> ClientState {
+        ClientState::new(client_capabilities(), data, 1.)
+    }
+
+    #[test]
+    fn stays_disabled_without_text_edit() {
+        assert_eq!(ime_transition(None, None, 1.), None);
+    }
+
+    #[test]
+    fn enables_with_all_capabilities() {
+        let Some(ImeRequest::Enable(enable)) = ime_transition(None, Some(editing(5., "foo")), 1.)
+        else {
+            panic!("expected the IME to be enabled");
+        };
+        assert_eq!(*enable.capabilities(), client_capabilities());
+        assert_eq!(*enable.request_data(), editing(5., "foo"));
+    }
+
+    #[test]
+    fn does_not_update_unchanged_state() {
+        let current = enabled(editing(5., "foo"));
+        assert_eq!(
+            ime_transition(Some(&current), Some(editing(5., "foo")), 1.),
+            None
+        );
+    }
+
+    #[test]
+    fn updates_moved_cursor_area() {
+        let current = enabled(editing(5., "foo"));
+        assert_eq!(
+            ime_transition(Some(&current), Some(editing(8., "foo")), 1.),
+            Some(ImeRequest::Update(editing(8., "foo")))
+        );
+    }
+
+    #[test]
+    fn updates_changed_surrounding_text() {
+        let current = enabled(editing(5., "foo"));
+        assert_eq!(
+            ime_transition(Some(&current), Some(editing(5., "foob")), 1.),
+            Some(ImeRequest::Update(editing(5., "foob")))
+        );
+    }
+
+    #[test]
+    fn disables_once_editing_stops() {
+        let current = enabled(editing(5., "foo"));
+        assert_eq!(
+            ime_transition(Some(&current), None, 1.),
+            Some(ImeRequest::Disable)
+        );
+    }
+
+    #[test]
+    fn does_not_enable_with_missing_data() {
+        let data =
+            ImeRequestData::default().with_hint_and_purpose(ImeHint::NONE, ImePurpose::Normal);
+        assert_eq!(ime_transition(None, Some(data), 1.), None);
+    }
+
+    #[test]
+    fn ignores_data_of_capabilities_not_enabled() {
+        let capabilities = ImeCapabilities::new().with_hint_and_purpose();
+        let data =
+            ImeRequestData::default().with_hint_and_purpose(ImeHint::NONE, ImePurpose::Password);
+        let mut state = ClientState::new(capabilities, data, 1.);
+        state.update(editing(5., "foo"), 1.);
+        assert_eq!(state.cursor_area(), None);
+        assert_eq!(state.surrounding_text(), None);
+        assert_eq!(
+            state.content_type(),
+            Some((ImeHint::NONE, ImePurpose::Normal).into())
+        );
+    }
+
+    #[test]
+    fn scales_cursor_area_to_logical() {
+        let data = ImeRequestData::default()
+            .with_hint_and_purpose(ImeHint::NONE, ImePurpose::Normal)
+            .with_cursor_area(
+                dpi::PhysicalPosition::new(20, 10).into(),
+                dpi::PhysicalSize::new(200, 40).into(),
+            )
+            .with_surrounding_text(ImeSurroundingText::empty());
+        let state = ClientState::new(client_capabilities(), data, 2.);
+        assert_eq!(
+            state.cursor_area(),
+            Some((LogicalPosition::new(10, 5), LogicalSize::new(100, 20)))
+        );
+    }
+}
@@ -11,9 +11,9 @@ use thiserror::Error;
 use wayland_backend::client::Backend;
 use wayland_client::{protocol::wl_surface::WlSurface, Proxy};
 use wgpu::{
-    Backends, CreateSurfaceError, Device, Instance, InstanceDescriptor, PresentMode, Queue,
-    RequestAdapterOptions, RequestDeviceError, Surface, SurfaceConfiguration, SurfaceTargetUnsafe,
-    TextureFormat, TextureUsages,
+    Adapter, Backends, CreateSurfaceError, Device, Instance, InstanceDescriptor, PresentMode,
+    Queue, RequestAdapterOptions, RequestDeviceError, Surface, SurfaceConfiguration,
+    SurfaceTargetUnsafe, TextureFormat, TextureUsages,
 };
 
 #[derive(Error, Debug)]
@@ -31,49 +31,89 @@ pub enum WgpuStateError {
 }
 
 pub struct WgpuState {
+    pub(crate) instance: Instance,
+    pub(crate) adapter: Adapter,
     pub(crate) device: Device,
-    pub(crate) surface_configuration: SurfaceConfiguration,
     pub(crate) queue: Queue,
+}
+
+/// The swapchain of a single layer surface. All of them share the device of [`WgpuState`].
+pub struct WgpuSurface {
+    pub(crate) surface_configuration: SurfaceConfiguration,
     pub(crate) surface: Surface<'static>,
 }
 use ext_background_effect_manager_v1::*;
 
+fn create_raw_surface(
+    instance: &Instance,
+    backend: &Backend,
+    wl_surface: &WlSurface,
+) -> Result<Surface<'static>, WgpuStateError> {
+    let w_display =
+        WaylandDisplayHandle::new(NonNull::new(backend.display_ptr() as *mut _).ok_or(
+            WgpuStateError::NullPointerError("display of backend".to_string()),
+        )?);
+    let raw_display_handle = RawDisplayHandle::Wayland(w_display);
+
+    let raw_window_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(
+        NonNull::new(wl_surface.id().as_ptr() as *mut _).ok_or(
+            WgpuStateError::NullPointerError("wl_surface id".to_string()),
+        )?,
+    ));
+
+    let surface = unsafe {
+        instance.create_surface_unsafe(SurfaceTargetUnsafe::RawHandle {
+            raw_display_handle,
+            raw_window_handle,
+        })?
+    };
+    Ok(surface)
+}
+
 impl WgpuState {
-    pub fn new(backend: &Backend, wl_surface: &WlSurface) -> Result<Self, WgpuStateError> {
+    /// Creates the shared device, using the first surface to pick a compatible adapter.
+    pub fn new(
+        backend: &Backend,
+        wl_surface: &WlSurface,
+    ) -> Result<(Self, WgpuSurface), WgpuStateError> {
         let instance = Instance::new(&InstanceDescriptor {
             backends: Backends::all(),
             ..Default::default()
         });
 
-        let w_display =
-            WaylandDisplayHandle::new(NonNull::new(backend.display_ptr() as *mut _).ok_or(
-                WgpuStateError::NullPointerError("display of backend".to_string()),
-            )?);
-        let raw_display_handle = RawDisplayHandle::Wayland(w_display);
-
-        let raw_window_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(
-            NonNull::new(wl_surface.id().as_ptr() as *mut _).ok_or(
-                WgpuStateError::NullPointerError("wl_surface id".to_string()),
-            )?,
-        ));
-
-        let surface = unsafe {
-            instance.create_surface_unsafe(SurfaceTargetUnsafe::RawHandle {
-                raw_display_handle,
-                raw_window_handle,
-            })?
-        };
+        let surface = create_raw_surface(&instance, backend, wl_surface)?;
 
         let adapter = pollster::block_on(instance.request_adapter(&RequestAdapterOptions {
             compatible_surface: Some(&surface),
             ..Default::default()
         }))
-        .unwrap();
+        .map_err(|_| WgpuStateError::NoAdapterError)?;
+
+        let (device, queue) = pollster::block_on(adapter.request_device(&Default::default()))?;
 
-        let (device, queue) =
-            pollster::block_on(adapter.request_device(&Default::default()))?;
+        let state = Self {
+            instance,
+            adapter,
+            device,
+            queue,
+        };
+        let surface = state.configure_surface(surface)?;
+
+        Ok((state, surface))
+    }
 
-        let surface_capabilities = surface.get_capabilities(&adapter);
+    /// Creates the swapchain for another layer surface on the shared device.
+    pub fn create_surface(
+        &self,
+        backend: &Backend,
+        wl_surface: &WlSurface,
+    ) -> Result<WgpuSurface, WgpuStateError> {
+        let surface = create_raw_surface(&self.instance, backend, wl_surface)?;
+        self.configure_surface(surface)
+    }
+
+    fn configure_surface(&self, surface: Surface<'static>) -> Result<WgpuSurface, WgpuStateError> {
+        let surface_capabilities = surface.get_capabilities(&self.adapter);
         let texture_format = surface_capabilities
             .formats
             .iter()
@@ -92,20 +132,19 @@ impl WgpuState {
             view_formats: vec![*texture_format],
         };
 
-        surface.configure(&device, &surface_configuration);
+        surface.configure(&self.device, &surface_configuration);
 
-        Ok(Self {
-            device,
+        Ok(WgpuSurface {
             surface_configuration,
-            queue,
             surface,
         })
     }
+}
 
-    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+impl WgpuSurface {
+    pub(crate) fn resize(&mut self, device: &Device, width: u32, height: u32) {
         self.surface_configuration.width = width;
         self.surface_configuration.height = height;
-        self.surface
-            .configure(&self.device, &self.surface_configuration);
+        self.surface.configure(device, &self.surface_configuration);
     }
 }